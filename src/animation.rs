@@ -0,0 +1,120 @@
+use std::rc::Rc;
+use crate::assets::{Atlas, Region};
+use crate::color::Color;
+use crate::framebuffer::Framebuffer;
+use crate::sprite_loader::Sprite;
+
+// A single frame, a region of a texture shown for some seconds
+pub struct Frame {
+    pub texture: Rc<Sprite>,
+    pub region: Region,
+    pub duration: f32,
+}
+
+impl Frame {
+    pub fn sample(&self, u: f32, v: f32) -> Color {
+        self.texture.sample_region(&self.region, u, v)
+    }
+
+    // Uses the mipmap level that matches the height on screen, bilinear filtered
+    pub fn sample_filtered(&self, u: f32, v: f32, screen_height: f32) -> Color {
+        let ratio = self.region.height as f32 / screen_height.max(1.0);
+        let lod = if ratio > 1.0 { ratio.log2() as usize } else { 0 };
+        let region = Region {
+            x: self.region.x >> lod,
+            y: self.region.y >> lod,
+            width: (self.region.width >> lod).max(1),
+            height: (self.region.height >> lod).max(1),
+        };
+        self.texture.level(lod).sample_bilinear(&region, u, v)
+    }
+}
+
+pub struct Animation {
+    frames: Vec<Frame>,
+    length: f32,
+}
+
+impl Animation {
+    // Splits a horizontal strip of a texture in equal frames, one per duration (in seconds)
+    pub fn from_strip(texture: Rc<Sprite>, region: Region, durations: &[f32]) -> Self {
+        let frame_width = region.width / durations.len().max(1);
+        let frames = durations
+            .iter()
+            .enumerate()
+            .map(|(i, duration)| Frame {
+                texture: Rc::clone(&texture),
+                region: Region { x: region.x + i * frame_width, width: frame_width, ..region },
+                duration: *duration,
+            })
+            .collect();
+        Animation::build(frames)
+    }
+
+    pub fn from_sheet(texture: Rc<Sprite>, durations: &[f32]) -> Self {
+        let region = Region::of(&texture);
+        Animation::from_strip(texture, region, durations)
+    }
+
+    // Uses a sequence of textures as frames,
+    // the last duration is reused when there are less durations than textures
+    pub fn from_frames(textures: Vec<Rc<Sprite>>, durations: &[f32]) -> Self {
+        let frames = textures
+            .into_iter()
+            .enumerate()
+            .map(|(i, texture)| Frame {
+                region: Region::of(&texture),
+                texture,
+                duration: durations[i.min(durations.len() - 1)],
+            })
+            .collect();
+        Animation::build(frames)
+    }
+
+    pub fn from_atlas(atlas: &Atlas, index: usize) -> Self {
+        Animation::build(vec![Frame {
+            texture: Rc::clone(&atlas.sheet),
+            region: atlas.regions[index],
+            duration: 1.0,
+        }])
+    }
+
+    pub fn still(texture: Rc<Sprite>) -> Self {
+        Animation::from_sheet(texture, &[1.0])
+    }
+
+    fn build(frames: Vec<Frame>) -> Self {
+        let length = frames.iter().map(|frame| frame.duration).sum();
+        Animation { frames, length }
+    }
+
+    // Frame to show at a given moment of the game clock, the animation loops
+    pub fn frame(&self, time: f32) -> &Frame {
+        if self.frames.len() == 1 || self.length <= 0.0 {
+            return &self.frames[0];
+        }
+        let mut t = time.rem_euclid(self.length);
+        for frame in &self.frames {
+            if t < frame.duration {
+                return frame;
+            }
+            t -= frame.duration;
+        }
+        &self.frames[self.frames.len() - 1]
+    }
+
+    pub fn render_screen(&self, framebuffer: &mut Framebuffer, time: f32) {
+        for x in 0..framebuffer.width - 1 {
+            // Transforming x to frame coordinates
+            let u = (x as f32) / framebuffer.width as f32;
+            let frame = self.frame(time);
+            for y in 0..framebuffer.height - 1 {
+                // Transforming y to frame coordinates
+                let v = (y as f32) / framebuffer.height as f32;
+                let color = Color::to_hex(&frame.sample(u, v));
+                framebuffer.set_current_color(color);
+                framebuffer.point(x, y);
+            }
+        }
+    }
+}
@@ -0,0 +1,129 @@
+use std::collections::HashMap;
+use std::rc::Rc;
+use crate::color::Color;
+use crate::sprite_loader::Sprite;
+
+// Rectangle of a texture, in pixels
+#[derive(Debug, Copy, Clone)]
+pub struct Region {
+    pub x: usize,
+    pub y: usize,
+    pub width: usize,
+    pub height: usize,
+}
+
+impl Region {
+    pub fn of(sprite: &Sprite) -> Self {
+        Region { x: 0, y: 0, width: sprite.width, height: sprite.height }
+    }
+}
+
+// Several small textures packed in a single sprite, regions follow the order they were asked in
+pub struct Atlas {
+    pub sheet: Rc<Sprite>,
+    pub regions: Vec<Region>,
+}
+
+// Keeps every texture decoded once, shared between all the screens
+pub struct AssetCache {
+    textures: HashMap<String, Rc<Sprite>>,
+    color_keys: HashMap<String, u32>,
+}
+
+impl AssetCache {
+    pub fn new() -> Self {
+        AssetCache { textures: HashMap::new(), color_keys: HashMap::new() }
+    }
+
+    // Pixels of this color will be transparent, has to be set before the texture is loaded.
+    // For images without an alpha channel, 32 bit ones bring their own
+    pub fn set_color_key(&mut self, path: &str, key: u32) {
+        self.color_keys.insert(path.to_string(), key);
+    }
+
+    pub fn load(&mut self, path: &str) -> Result<Rc<Sprite>, String> {
+        if let Some(texture) = self.textures.get(path) {
+            return Ok(Rc::clone(texture));
+        }
+        let mut texture = Sprite::load(path)?;
+        if let Some(key) = self.color_keys.get(path) {
+            texture.apply_color_key(*key);
+        }
+        let texture = Rc::new(texture);
+        self.textures.insert(path.to_string(), Rc::clone(&texture));
+        Ok(texture)
+    }
+
+    // Packs the textures in shelves, tallest first, inside a power of two wide sheet
+    pub fn atlas(&mut self, paths: &[&str]) -> Result<Atlas, String> {
+        let key = paths.join("|");
+        let textures = paths
+            .iter()
+            .map(|path| self.load(path))
+            .collect::<Result<Vec<_>, String>>()?;
+
+        let area: usize = textures.iter().map(|t| t.width * t.height).sum();
+        let widest = textures.iter().map(|t| t.width).max().unwrap_or(0);
+        let atlas_width = widest.max((area as f32).sqrt().ceil() as usize).next_power_of_two();
+
+        let mut order: Vec<usize> = (0..textures.len()).collect();
+        order.sort_by(|a, b| textures[*b].height.cmp(&textures[*a].height));
+
+        let mut regions = vec![Region { x: 0, y: 0, width: 0, height: 0 }; textures.len()];
+        let (mut x, mut y, mut shelf_height) = (0, 0, 0);
+        for i in order {
+            let texture = &textures[i];
+            if x + texture.width > atlas_width {
+                x = 0;
+                y += shelf_height;
+                shelf_height = 0;
+            }
+            regions[i] = Region { x, y, width: texture.width, height: texture.height };
+            x += texture.width;
+            shelf_height = shelf_height.max(texture.height);
+        }
+        let atlas_height = y + shelf_height;
+
+        if let Some(sheet) = self.textures.get(&key) {
+            return Ok(Atlas { sheet: Rc::clone(sheet), regions });
+        }
+
+        let mut buffer = vec![Color::new(0, 0, 0); atlas_width * atlas_height];
+        for (texture, region) in textures.iter().zip(&regions) {
+            for tx in 0..texture.width {
+                let start = (region.x + tx) * atlas_height + region.y;
+                buffer[start..start + texture.height].copy_from_slice(texture.column(tx));
+            }
+        }
+        // Atlases hold the wall textures, those get mipmaps for distant walls
+        let mut sheet = Sprite { buffer, height: atlas_height, width: atlas_width, mips: Vec::new() };
+        sheet.build_mipmaps();
+        let sheet = Rc::new(sheet);
+        self.textures.insert(key, Rc::clone(&sheet));
+        Ok(Atlas { sheet, regions })
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    // A 24 bit image with a white background
+    const SANDWICH: &str = "./src/sprites/prizes/sandwich.bmp";
+
+    #[test]
+    fn color_key_makes_pixels_transparent() {
+        let mut assets = AssetCache::new();
+        assets.set_color_key(SANDWICH, 0xFFFFFF);
+        let keyed = assets.load(SANDWICH).unwrap();
+        assert_eq!(keyed.buffer[0].a, 0);
+        assert!(keyed.buffer.iter().all(|pixel| (pixel.a == 0) == (pixel.to_hex() == 0xFFFFFF)));
+        assert!(keyed.buffer.iter().any(|pixel| pixel.a == 255));
+    }
+
+    #[test]
+    fn no_color_key_stays_opaque() {
+        let sandwich = AssetCache::new().load(SANDWICH).unwrap();
+        assert!(sandwich.buffer.iter().all(|pixel| pixel.a == 255));
+    }
+}
@@ -1,10 +1,13 @@
-use rodio::{Decoder, OutputStream, Sink};
+use rodio::source::{SineWave, Source};
+use rodio::{Decoder, OutputStream, OutputStreamHandle, Sink};
 use std::fs::File;
 use std::io::BufReader;
 use std::sync::{Arc, Mutex};
+use std::time::Duration;
 
 pub struct AudioPlayer {
     sink: Arc<Mutex<Sink>>,
+    handle: OutputStreamHandle,
     _stream: OutputStream,
 }
 
@@ -20,6 +23,7 @@ impl AudioPlayer {
 
         AudioPlayer {
             sink: Arc::new(Mutex::new(sink)),
+            handle: stream_handle,
             _stream: stream,
         }
     }
@@ -27,4 +31,12 @@ impl AudioPlayer {
     pub fn play(&self) {
         self.sink.lock().unwrap().play();
     }
-}
\ No newline at end of file
+
+    // Short sound effect played over the music
+    pub fn play_tone(&self, frequency: f32, duration: Duration) {
+        if let Ok(effect) = Sink::try_new(&self.handle) {
+            effect.append(SineWave::new(frequency).take_duration(duration).amplify(0.2));
+            effect.detach();
+        }
+    }
+}
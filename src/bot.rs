@@ -0,0 +1,96 @@
+use core::f32::consts::PI;
+use crate::level::{is_floor, is_hazard, is_key, is_low_ceiling, is_portal, Level};
+use crate::pathfinding::find_path;
+use crate::player::{Input, Player};
+
+// Fastest the bot turns, in radians per second
+const TURN_SPEED: f32 = 4.0;
+// How straight it has to face a door to open it
+const DOOR_ANGLE: f32 = 0.3;
+
+// Plays the level on its own: it walks the shortest path to the goal, going for keys first
+// when a locked door is in the way, and keeps off hazards when it can
+#[derive(Default)]
+pub struct Bot {
+    // Cells from where it planned to the end of the path
+    path: Vec<(usize, usize)>,
+}
+
+impl Bot {
+    // What to press on this frame
+    pub fn input(&mut self, level: &Level, player: &Player, delta_time: f32) -> Input {
+        let here = level.cell(player.pos.x, player.pos.y);
+        // A new plan at the end of each path and whenever it ends up off it, like after
+        // stepping on a teleporter on the way
+        if heading(&self.path, here).is_none() {
+            self.path = plan(level, player);
+        }
+        let mut input = Input::default();
+        let Some(next) = heading(&self.path, here) else {
+            return input;
+        };
+
+        let delta = level.center(next) - player.pos;
+        let off = (delta.y.atan2(delta.x) - player.a + PI).rem_euclid(2.0 * PI) - PI;
+        let max_turn = TURN_SPEED * delta_time;
+        input.turn = player.turn_for(off.clamp(-max_turn, max_turn));
+        // Walks straight to the next cell, whichever way it faces
+        input.direction.x = off.cos();
+        input.direction.y = off.sin();
+        input.crouch = [here, next].iter().any(|(i, j)| is_low_ceiling(level.maze[*i][*j]));
+        input.interact = off.abs() < DOOR_ANGLE
+            && level.doors.get(&next).is_some_and(|door| !door.opening());
+        input
+    }
+}
+
+// Cell to walk to from `here` along a path, None when `here` isn't on it or is its end.
+// A step through a teleporter or portal lands far away, so it's its entrance
+pub fn heading(path: &[(usize, usize)], here: (usize, usize)) -> Option<(usize, usize)> {
+    let at = path.iter().rposition(|cell| *cell == here)?;
+    let next = *path.get(at + 1)?;
+    let linked = here.0.abs_diff(next.0) + here.1.abs_diff(next.1) > 1;
+    Some(if linked { path[at] } else { next })
+}
+
+// Shortest path from the player's cell to the goal, or to the nearest key when the goal
+// can't be reached yet. Hazards are only crossed when there's no other way
+pub fn plan(level: &Level, player: &Player) -> Vec<(usize, usize)> {
+    let maze = &level.maze;
+    let here = level.cell(player.pos.x, player.pos.y);
+    let Some(goal) = (0..maze.len())
+        .flat_map(|i| (0..maze[i].len()).map(move |j| (i, j)))
+        .find(|(i, j)| maze[*i][*j] == 'g')
+    else {
+        return Vec::new();
+    };
+    let keys: Vec<(usize, usize)> = level.items.iter().map(|item| level.cell(item.pos.x, item.pos.y)).collect();
+    // Portals come out of their pair, in front of it
+    let mut links = level.teleporters.clone();
+    links.extend(level.portals.iter().map(|(cell, portal)| (*cell, portal.to)));
+    // The teleporter the player just arrived on doesn't work until they step out of it
+    links.remove(&here);
+
+    for safe in [true, false] {
+        let passable = |cell: (usize, usize), tile: char| {
+            if safe && is_hazard(tile) {
+                return false;
+            }
+            match level.doors.get(&cell) {
+                Some(door) => door.lock.is_none_or(|key| player.inventory.contains(&key)),
+                None => is_floor(tile) || is_key(tile) || is_portal(tile) || is_low_ceiling(tile),
+            }
+        };
+        if let Some(path) = find_path(maze, here, goal, &links, passable) {
+            return path;
+        }
+        if let Some(path) = keys
+            .iter()
+            .filter_map(|key| find_path(maze, here, *key, &links, passable))
+            .min_by_key(|path| path.len())
+        {
+            return path;
+        }
+    }
+    Vec::new()
+}
@@ -3,6 +3,8 @@ pub struct Color {
     pub r: u8,
     pub g: u8,
     pub b: u8,
+    // 255 is opaque, 0 is fully transparent
+    pub a: u8,
 }
 
 impl Color {
@@ -10,7 +12,8 @@ impl Color {
         Color {
             r: (r as f32).clamp(0.0, 255.0) as u8,
             g: (g as f32).clamp(0.0, 255.0) as u8,
-            b: (b as f32).clamp(0.0, 255.0) as u8
+            b: (b as f32).clamp(0.0, 255.0) as u8,
+            a: 255,
         }
     }
 
@@ -18,12 +21,27 @@ impl Color {
         let r = (hex >> 16 & 0xFF) as u8;
         let g = (hex >> 8 & 0xFF) as u8;
         let b = (hex & 0xFF) as u8;
-        Color {r, g, b}
+        Color {r, g, b, a: 255}
+    }
+
+    pub fn with_alpha(self, a: u8) -> Color {
+        Color { a, ..self }
     }
 
     pub fn to_hex(&self) -> u32 {
         ((self.r as u32)<< 16) | ((self.g as u32) << 8) | (self.b as u32)
     }
+
+    // Mix between two colors, t = 0 gives a and t = 1 gives b
+    pub fn lerp(a: Color, b: Color, t: f32) -> Color {
+        let mix = |x: u8, y: u8| (x as f32 + (y as f32 - x as f32) * t).round() as u8;
+        Color { r: mix(a.r, b.r), g: mix(a.g, b.g), b: mix(a.b, b.b), a: mix(a.a, b.a) }
+    }
+
+    // Draws this color over another one using its alpha, the result is opaque
+    pub fn over(self, background: Color) -> Color {
+        Color::lerp(background, self.with_alpha(255), self.a as f32 / 255.0).with_alpha(255)
+    }
 }
 
 impl std::ops::Add for Color{
@@ -33,7 +51,7 @@ impl std::ops::Add for Color{
         let r = self.r.saturating_add(other.r);
         let g = self.g.saturating_add(other.g);
         let b = self.b.saturating_add(other.b);
-        Color { r, g, b}
+        Color { r, g, b, a: self.a}
     }
 }
 
@@ -44,6 +62,6 @@ impl std::ops::Mul<f32> for Color {
         let r = (self.r as f32 * factor).clamp(0.0, 255.0) as u8;
         let g = (self.g as f32 * factor).clamp(0.0, 255.0) as u8;
         let b = (self.b as f32 * factor).clamp(0.0, 255.0) as u8;
-        Color { r, g, b}
+        Color { r, g, b, a: self.a}
     }
-}
\ No newline at end of file
+}
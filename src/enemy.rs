@@ -0,0 +1,226 @@
+use std::collections::HashMap;
+use core::f32::consts::PI;
+use nalgebra_glm::Vec2;
+use crate::level::{is_door, is_floor, Level};
+use crate::pathfinding::{DistanceField, PathCache};
+use crate::player::Player;
+use crate::ray_caster::line_of_sight;
+
+// Speeds and ranges are in blocks, times in seconds
+const PATROL_SPEED: f32 = 1.2;
+const CHASE_SPEED: f32 = 3.0;
+const SIGHT_RANGE: f32 = 5.0;
+// Half the angle an enemy sees, around where it faces
+const VIEW_CONE: f32 = PI / 3.0;
+const CATCH_RANGE: f32 = 0.4;
+pub const CATCH_DAMAGE: f32 = 25.0;
+// Time between two hits on the player
+const CATCH_COOLDOWN: f32 = 1.0;
+// Time spent waiting at each waypoint
+const IDLE_TIME: f32 = 1.5;
+// Time spent looking around where the player was last seen or heard
+const SEARCH_TIME: f32 = 3.0;
+// How fast it turns while looking around, in radians per second
+const LOOK_SPEED: f32 = 1.5;
+// How far from its center an enemy keeps from walls
+const RADIUS: f32 = 0.2;
+
+#[derive(Debug, Copy, Clone, PartialEq)]
+pub enum EnemyState {
+    Idle,
+    Patrol,
+    Chase,
+    // Going where the player was last seen or heard, then looking around
+    Search,
+    // Going back to the route after losing the player
+    Return,
+}
+
+pub struct Enemy {
+    pub pos: Vec2,
+    pub state: EnemyState,
+    // Where it faces, it only sees what's in front
+    a: f32,
+    // Cell it searches
+    clue: (usize, usize),
+    // Cells it walks through in order, its spawn first
+    route: Vec<(usize, usize)>,
+    next: usize,
+    wait: f32,
+    cooldown: f32,
+}
+
+// Enemies start on 'e' tiles. Each 'w' waypoint joins the route of the closest enemy by walking,
+// which goes through them nearest first and then back to the start
+pub fn spawn_enemies(level: &Level) -> Vec<Enemy> {
+    let maze = &level.maze;
+    let cells = |tile: char| {
+        (0..maze.len())
+            .flat_map(|i| (0..maze[i].len()).map(move |j| (i, j)))
+            .filter(move |(i, j)| maze[*i][*j] == tile)
+    };
+    // Walking distance through floors and doors, cells cut off from each other are the farthest
+    let fields: HashMap<(usize, usize), DistanceField> = cells('e')
+        .chain(cells('w'))
+        .map(|cell| (cell, DistanceField::new(maze, cell, &HashMap::new(), |_, tile| is_floor(tile) || is_door(tile))))
+        .collect();
+    let distance = |a: (usize, usize), b: (usize, usize)| fields[&a].distance(b).unwrap_or(usize::MAX);
+
+    let spawns: Vec<(usize, usize)> = cells('e').collect();
+    let mut routes: Vec<Vec<(usize, usize)>> = spawns.iter().map(|spawn| vec![*spawn]).collect();
+    let mut waypoints: Vec<(usize, usize)> = cells('w').collect();
+    for route in routes.iter_mut() {
+        let spawn = route[0];
+        let mut mine: Vec<(usize, usize)> = Vec::new();
+        waypoints.retain(|waypoint| {
+            let closest = spawns.iter().min_by_key(|other| distance(**other, *waypoint));
+            if closest == Some(&spawn) {
+                mine.push(*waypoint);
+                false
+            } else {
+                true
+            }
+        });
+        while let Some(n) = (0..mine.len()).min_by_key(|n| distance(*route.last().unwrap(), mine[*n])) {
+            route.push(mine.remove(n));
+        }
+    }
+
+    routes
+        .into_iter()
+        .map(|route| Enemy {
+            pos: level.center(route[0]),
+            state: EnemyState::Idle,
+            a: 0.0,
+            clue: route[0],
+            next: 0,
+            route,
+            wait: IDLE_TIME,
+            cooldown: 0.0,
+        })
+        .collect()
+}
+
+impl Enemy {
+    // Moves the enemy for this frame, returns the health the player loses.
+    // `heard` is where the sound it heard this frame came from
+    pub fn update(
+        &mut self,
+        level: &Level,
+        paths: &mut PathCache,
+        player: &Player,
+        heard: Option<(usize, usize)>,
+        delta_time: f32,
+    ) -> f32 {
+        let block_size = level.block_size as f32;
+        let to_player = (player.pos - self.pos).magnitude() / block_size;
+        self.cooldown = (self.cooldown - delta_time).max(0.0);
+
+        if self.sees(level, player) {
+            self.clue = level.cell(player.pos.x, player.pos.y);
+            self.state = EnemyState::Chase;
+        } else if let Some(cell) = heard.filter(|_| self.state != EnemyState::Chase) {
+            self.clue = cell;
+            self.state = EnemyState::Search;
+            self.wait = SEARCH_TIME;
+        } else if self.state == EnemyState::Chase {
+            // Lost sight of the player, off to the last place it saw them
+            self.state = EnemyState::Search;
+            self.wait = SEARCH_TIME;
+        }
+
+        match self.state {
+            EnemyState::Idle => {
+                self.wait -= delta_time;
+                self.a += LOOK_SPEED * delta_time;
+                if self.wait <= 0.0 && self.route.len() > 1 {
+                    self.next = (self.next + 1) % self.route.len();
+                    self.state = EnemyState::Patrol;
+                }
+            },
+            EnemyState::Patrol | EnemyState::Return => {
+                let cell = self.route[self.next];
+                if self.head_to(level, paths, cell, level.center(cell), PATROL_SPEED * block_size * delta_time) {
+                    self.state = EnemyState::Idle;
+                    self.wait = IDLE_TIME;
+                }
+            },
+            EnemyState::Search => {
+                let cell = self.clue;
+                if self.head_to(level, paths, cell, level.center(cell), CHASE_SPEED * block_size * delta_time) {
+                    self.wait -= delta_time;
+                    self.a += LOOK_SPEED * delta_time;
+                    if self.wait <= 0.0 {
+                        self.state = EnemyState::Return;
+                    }
+                }
+            },
+            EnemyState::Chase => {
+                let cell = level.cell(player.pos.x, player.pos.y);
+                self.head_to(level, paths, cell, player.pos, CHASE_SPEED * block_size * delta_time);
+            },
+        }
+
+        if to_player <= CATCH_RANGE && self.cooldown == 0.0 {
+            self.cooldown = CATCH_COOLDOWN;
+            return CATCH_DAMAGE;
+        }
+        0.0
+    }
+
+    // The player is seen when close enough, inside the view cone and not hidden by walls
+    fn sees(&self, level: &Level, player: &Player) -> bool {
+        let delta = player.pos - self.pos;
+        if delta.magnitude() > SIGHT_RANGE * level.block_size as f32 {
+            return false;
+        }
+        let off = (delta.y.atan2(delta.x) - self.a + PI).rem_euclid(2.0 * PI) - PI;
+        off.abs() <= VIEW_CONE && line_of_sight(level, self.pos, player.pos, player.eye_height())
+    }
+
+    // Follows the shortest path to the cell holding `target` through floors and open doors,
+    // then walks straight to it. Returns true once it got there, or when there's no way there
+    fn head_to(&mut self, level: &Level, paths: &mut PathCache, cell: (usize, usize), target: Vec2, step: f32) -> bool {
+        let here = level.cell(self.pos.x, self.pos.y);
+        if here == cell {
+            return self.walk(level, target, step);
+        }
+        let next = paths
+            .toward(&level.maze, cell, &HashMap::new(), |cell, _| level.is_open_cell(cell))
+            .next(here);
+        match next {
+            Some(next) => {
+                self.walk(level, level.center(next), step);
+                false
+            },
+            None => true,
+        }
+    }
+
+    // Moves up to `step` pixels towards a point, sliding along walls in the way.
+    // Returns true once it got there
+    fn walk(&mut self, level: &Level, target: Vec2, step: f32) -> bool {
+        let delta = target - self.pos;
+        let distance = delta.magnitude();
+        let motion = if distance <= step { delta } else { delta / distance * step };
+        if distance > 0.0 {
+            self.a = delta.y.atan2(delta.x);
+        }
+        let radius = RADIUS * level.block_size as f32;
+        let free = |pos: Vec2| {
+            [(-1.0, -1.0), (-1.0, 1.0), (1.0, -1.0), (1.0, 1.0)]
+                .iter()
+                .all(|(dx, dy)| level.is_open(pos.x + dx * radius, pos.y + dy * radius))
+        };
+
+        let along_x = Vec2::new(self.pos.x + motion.x, self.pos.y);
+        if free(along_x) {
+            self.pos = along_x;
+        }
+        let along_y = Vec2::new(self.pos.x, self.pos.y + motion.y);
+        if free(along_y) {
+            self.pos = along_y;
+        }
+        (target - self.pos).magnitude() < 1.0
+    }
+}
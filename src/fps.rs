@@ -1,6 +1,8 @@
 use crate::framebuffer::Framebuffer;
+use crate::color::Color;
 
 pub fn render_fps(framebuffer: &mut Framebuffer, numbers: &Vec<Vec<char>>, num: usize){
+    render_panel(framebuffer, 144, 0, 114, 27, Color::new(0, 0, 0).with_alpha(120));
     framebuffer.set_current_color(0xffffff);
     text_format(framebuffer, numbers, num);
 }
@@ -13,6 +15,11 @@ pub fn text_format(framebuffer: &mut Framebuffer, numbers: &Vec<Vec<char>>, num:
             }  
         }
     }
+    render_number(framebuffer, numbers, num, 195, 3);
+}
+
+// Draws a number with its top left corner at (xo, yo), each digit is 12 pixels wide
+pub fn render_number(framebuffer: &mut Framebuffer, numbers: &[Vec<char>], num: usize, xo: usize, yo: usize){
     let num_str: Vec<char>= num.to_string().chars().rev().collect();
     let size = num_str.len();
     for digit_pos in 0..size{
@@ -20,12 +27,11 @@ pub fn text_format(framebuffer: &mut Framebuffer, numbers: &Vec<Vec<char>>, num:
         for i in 0..6{
             for col in 0..numbers[(6*(digit+1))+i-1].len(){
                 if numbers[(6*(digit+1))+i-1][col]!=' '{
-                    render_box(framebuffer, (i+1)*3, (col+50)*3+(45+12*digit_pos), 3, 3);
+                    render_box(framebuffer, yo+i*3, xo+col*3+12*digit_pos, 3, 3);
                 }  
             }
         }
     }
-
 }
 
 pub fn render_box(framebuffer: &mut Framebuffer, xo: usize, yo: usize, w: usize, h: usize){
@@ -34,4 +40,64 @@ pub fn render_box(framebuffer: &mut Framebuffer, xo: usize, yo: usize, w: usize,
             framebuffer.point(j, i);
         }
     }
+}
+
+// Semi-transparent rectangle to put behind HUD elements
+pub fn render_panel(framebuffer: &mut Framebuffer, xo: usize, yo: usize, w: usize, h: usize, color: Color){
+    for i in yo..yo+h{
+        for j in xo..xo+w{
+            framebuffer.blend_point(j, i, color);
+        }
+    }
+}
+
+// A meter filled from the left, `fill` goes from 0 to 1
+pub fn render_bar(framebuffer: &mut Framebuffer, xo: usize, yo: usize, w: usize, h: usize, fill: f32, color: Color){
+    render_panel(framebuffer, xo, yo, w, h, Color::new(0, 0, 0).with_alpha(150));
+    let filled = ((w - 2) as f32 * fill.clamp(0.0, 1.0)) as usize;
+    render_panel(framebuffer, xo + 1, yo + 1, filled, h - 2, color);
+}
+
+// Arrow pointing `angle` radians clockwise from straight up, `size` pixels from its middle to the tip
+pub fn render_arrow(framebuffer: &mut Framebuffer, xo: usize, yo: usize, size: f32, angle: f32, color: Color){
+    let (sin, cos) = angle.sin_cos();
+    let reach = size.ceil() as i32;
+    for dy in -reach..=reach{
+        for dx in -reach..=reach{
+            // Distances towards the tip and across the arrow, in sizes
+            let along = (dx as f32 * sin - dy as f32 * cos) / size;
+            let across = ((dx as f32 * cos + dy as f32 * sin) / size).abs();
+            let head = (0.0..=1.0).contains(&along) && across <= (1.0 - along) * 0.6;
+            let shaft = (-1.0..0.0).contains(&along) && across <= 0.2;
+            if head || shaft{
+                framebuffer.blend_point((xo as i32 + dx) as usize, (yo as i32 + dy) as usize, color);
+            }
+        }
+    }
+}
+
+// Draws one of the compass letters (N, E, S or W), each pixel of it a `scale` sided square
+pub fn render_letter(framebuffer: &mut Framebuffer, letter: char, xo: usize, yo: usize, scale: usize){
+    let glyph = match letter {
+        'N' => ["#...#", "##..#", "#.#.#", "#..##", "#...#"],
+        'E' => ["#####", "#....", "####.", "#....", "#####"],
+        'S' => [".####", "#....", ".###.", "....#", "####."],
+        'W' => ["#...#", "#...#", "#.#.#", "##.##", "#...#"],
+        _ => return,
+    };
+    for (i, row) in glyph.iter().enumerate(){
+        for (j, pixel) in row.chars().enumerate(){
+            if pixel == '#'{
+                render_box(framebuffer, yo + i * scale, xo + j * scale, scale, scale);
+            }
+        }
+    }
+}
+
+// Tints the whole screen, strength goes from 0 (nothing) to 1
+pub fn render_flash(framebuffer: &mut Framebuffer, color: Color, strength: f32){
+    if strength > 0.0 {
+        let alpha = (strength.min(1.0) * 200.0) as u8;
+        render_panel(framebuffer, 0, 0, framebuffer.width, framebuffer.height, color.with_alpha(alpha));
+    }
 }
\ No newline at end of file
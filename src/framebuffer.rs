@@ -36,6 +36,24 @@ impl Framebuffer {
             self.buffer[index] = self.current_color;
         }
     }
+    pub fn line(&mut self, x0: usize, y0: usize, x1: usize, y1: usize){
+        let steps = (x1 as f32 - x0 as f32).abs().max((y1 as f32 - y0 as f32).abs()).max(1.0);
+        for n in 0..=steps as usize{
+            let t = n as f32 / steps;
+            let x = x0 as f32 + (x1 as f32 - x0 as f32) * t;
+            let y = y0 as f32 + (y1 as f32 - y0 as f32) * t;
+            self.point(x.round() as usize, y.round() as usize);
+        }
+    }
+
+    // Mixes a color with what is already drawn, using the color's alpha
+    pub fn blend_point(&mut self, x: usize, y: usize, color: Color){
+        if x< self.width && y< self.height && color.a > 0 {
+            let index = y*self.width +x;
+            self.buffer[index] = color.over(self.buffer[index]);
+        }
+    }
+
     pub fn color_array_to_u32(&mut self) -> Vec<u32> {
         self.buffer.iter().map(|color| {
             ((color.r as u32) << 16) | ((color.g as u32) << 8) | (color.b as u32)
@@ -0,0 +1,562 @@
+use std::collections::HashMap;
+use core::f32::consts::PI;
+use nalgebra_glm::{rotate_vec2, Vec2};
+use crate::player::Player;
+use crate::enemy::{spawn_enemies, Enemy};
+use crate::noise::{spread, DOOR_NOISE};
+use crate::pathfinding::PathCache;
+
+const DOOR_SPEED: f32 = 1.5;
+const PUSH_SPEED: f32 = 1.0;
+const PUSH_CELLS: i32 = 2;
+// Electrified floors are live for the first half of each period, in seconds
+const ELECTRIC_PERIOD: f32 = 2.0;
+
+// A sliding door, drawn as a thin wall in the middle of its cell
+#[derive(Clone)]
+pub struct Door {
+    // true when the door goes from wall to wall along x, so it is crossed moving in y
+    pub along_x: bool,
+    // 0 is closed and 1 is fully open
+    pub open: f32,
+    target: f32,
+    // Key needed to open it, if any
+    pub lock: Option<char>,
+}
+
+impl Door {
+    // Whether it's open or on its way to it
+    pub fn opening(&self) -> bool {
+        self.target > 0.5
+    }
+}
+
+// A secret wall sliding away after being pushed, it leaves the grid while it moves
+#[derive(Clone)]
+pub struct PushWall {
+    pub from: (usize, usize),
+    // Step between cells, like (1, 0)
+    pub direction: (i32, i32),
+    pub cells: i32,
+    // Cells travelled so far
+    pub offset: f32,
+}
+
+// A see-through doorway, what goes in comes out of the linked portal
+pub struct Portal {
+    pub to: (usize, usize),
+    // Side it's entered from, like (0, 1)
+    pub facing: (i32, i32),
+}
+
+#[derive(Debug, Default, Copy, Clone)]
+pub struct Stats {
+    pub secrets_found: usize,
+    pub secrets_total: usize,
+}
+
+// Something lying on the floor that the player picks up by walking over it
+#[derive(Clone)]
+pub struct Item {
+    pub pos: Vec2,
+    pub kind: char,
+}
+
+// What a 'c' tile remembers, the player comes back to it after dying
+#[derive(Clone)]
+pub struct Checkpoint {
+    pub cell: (usize, usize),
+    pub a: f32,
+    pub inventory: Vec<char>,
+    // Items still lying around when it was saved
+    pub items: Vec<Item>,
+    // Doors, pushed walls and secrets as they were, pushed walls change the grid
+    pub maze: Vec<Vec<char>>,
+    pub doors: HashMap<(usize, usize), Door>,
+    pub push_walls: Vec<PushWall>,
+    pub stats: Stats,
+}
+
+// The maze grid plus the state of the cells that change while playing
+pub struct Level {
+    pub maze: Vec<Vec<char>>,
+    pub block_size: usize,
+    pub doors: HashMap<(usize, usize), Door>,
+    pub items: Vec<Item>,
+    // Each teleporter cell points to the other cell of its pair
+    pub teleporters: HashMap<(usize, usize), (usize, usize)>,
+    pub portals: HashMap<(usize, usize), Portal>,
+    // Height of the tallest wall, the view goes on past walls lower than it
+    pub tallest: f32,
+    // Where the player last arrived, it doesn't send them back until they step out
+    arrived_at: Option<(usize, usize)>,
+    pub push_walls: Vec<PushWall>,
+    pub stats: Stats,
+    // Seconds since the level started
+    pub clock: f32,
+    pub enemies: Vec<Enemy>,
+    // Distance fields for the enemies, cleared whenever a door or wall moves
+    pub paths: PathCache,
+    // Sounds made this frame, by cell and loudness
+    noises: Vec<((usize, usize), f32)>,
+}
+
+// Tiles the player can walk and see through
+pub fn is_floor(tile: char) -> bool {
+    matches!(tile, ' ' | 'p' | 'g' | 'c' | 'e' | 'w') || is_teleporter(tile) || is_hazard(tile)
+}
+
+pub fn is_checkpoint(tile: char) -> bool {
+    tile == 'c'
+}
+
+// Floors that hurt: '^' spikes, '~' lava and '!' electrified floor
+pub fn is_hazard(tile: char) -> bool {
+    matches!(tile, '^' | '~' | '!')
+}
+
+// Health lost per second standing on a tile, electrified floors switch on and off
+pub fn hazard_damage(tile: char, time: f32) -> f32 {
+    match tile {
+        '^' => 40.0,
+        '~' => 80.0,
+        '!' if is_live(time) => 60.0,
+        _ => 0.0,
+    }
+}
+
+pub fn is_live(time: f32) -> bool {
+    time.rem_euclid(ELECTRIC_PERIOD) < ELECTRIC_PERIOD / 2.0
+}
+
+// Teleporters come in pairs, both cells use the same digit
+pub fn is_teleporter(tile: char) -> bool {
+    tile.is_ascii_digit()
+}
+
+// Portals come in pairs too, both cells use the same letter from 'h' to 'o'
+pub fn is_portal(tile: char) -> bool {
+    ('h'..='o').contains(&tile)
+}
+
+// Keys are lowercase letters, the doors they open are the same letter in uppercase
+pub fn is_key(tile: char) -> bool {
+    matches!(tile, 'r' | 'b' | 'y')
+}
+
+// Secret walls look like '|' walls but slide back when pushed
+pub fn is_secret(tile: char) -> bool {
+    tile == '%'
+}
+
+// Height of a wall compared to a regular block, '_' walls can be seen over and 'T' towers stand taller.
+// ':' is only the top half of a block, a low ceiling to crawl under
+pub fn wall_height(tile: char) -> f32 {
+    match tile {
+        '_' | ':' => 0.5,
+        'T' => 2.0,
+        _ => 1.0,
+    }
+}
+
+// Height where a wall starts, only low ceilings don't reach the floor
+pub fn wall_base(tile: char) -> f32 {
+    if is_low_ceiling(tile) { 0.5 } else { 0.0 }
+}
+
+pub fn is_low_ceiling(tile: char) -> bool {
+    tile == ':'
+}
+
+// Glass '=' and grates '*' block the player but let the view go through
+pub fn is_see_through(tile: char) -> bool {
+    matches!(tile, '=' | '*')
+}
+
+// Mirrors 'M' are solid, the view bounces off them
+pub fn is_mirror(tile: char) -> bool {
+    tile == 'M'
+}
+
+pub fn is_door(tile: char) -> bool {
+    matches!(tile, 'D' | 'R' | 'B' | 'Y')
+}
+
+impl Level {
+    pub fn new(mut maze: Vec<Vec<char>>, block_size: usize) -> Self {
+        let mut doors = HashMap::new();
+        let mut items = Vec::new();
+        let mut teleporters = HashMap::new();
+        let mut links = Vec::new();
+        let mut unpaired: HashMap<char, (usize, usize)> = HashMap::new();
+        let mut stats = Stats::default();
+        for i in 0..maze.len() {
+            for j in 0..maze[i].len() {
+                if is_key(maze[i][j]) {
+                    let size = block_size as f32;
+                    items.push(Item {
+                        pos: Vec2::new((i as f32 + 0.5) * size, (j as f32 + 0.5) * size),
+                        kind: maze[i][j],
+                    });
+                    maze[i][j] = ' ';
+                } else if is_door(maze[i][j]) {
+                    let wall_at = |i: usize, j: usize| {
+                        maze.get(i).and_then(|row| row.get(j)).is_some_and(|tile| !is_floor(*tile))
+                    };
+                    let along_x = i > 0 && wall_at(i - 1, j) && wall_at(i + 1, j);
+                    let lock = if maze[i][j] == 'D' { None } else { Some(maze[i][j].to_ascii_lowercase()) };
+                    doors.insert((i, j), Door { along_x, open: 0.0, target: 0.0, lock });
+                } else if is_teleporter(maze[i][j]) {
+                    if let Some(other) = unpaired.remove(&maze[i][j]) {
+                        teleporters.insert(other, (i, j));
+                        teleporters.insert((i, j), other);
+                    } else {
+                        unpaired.insert(maze[i][j], (i, j));
+                    }
+                } else if is_portal(maze[i][j]) {
+                    if let Some(other) = unpaired.remove(&maze[i][j]) {
+                        links.push((other, (i, j)));
+                        links.push(((i, j), other));
+                    } else {
+                        unpaired.insert(maze[i][j], (i, j));
+                    }
+                } else if is_secret(maze[i][j]) {
+                    stats.secrets_total += 1;
+                }
+            }
+        }
+        let portals = links
+            .into_iter()
+            .map(|(from, to)| (from, Portal { to, facing: open_side(&maze, from).unwrap_or((1, 0)) }))
+            .collect();
+        let tallest = maze.iter().flatten().map(|tile| wall_height(*tile)).fold(1.0, f32::max);
+        let mut level = Level {
+            maze,
+            block_size,
+            doors,
+            items,
+            teleporters,
+            portals,
+            tallest,
+            arrived_at: None,
+            push_walls: Vec::new(),
+            stats,
+            clock: 0.0,
+            enemies: Vec::new(),
+            paths: PathCache::default(),
+            noises: Vec::new(),
+        };
+        level.enemies = spawn_enemies(&level);
+        level
+    }
+
+    pub fn center(&self, cell: (usize, usize)) -> Vec2 {
+        let size = self.block_size as f32;
+        Vec2::new((cell.0 as f32 + 0.5) * size, (cell.1 as f32 + 0.5) * size)
+    }
+
+    // Sends the player to the linked teleporter, returns true when it happened.
+    // The facing angle is kept unless it points into a wall, then it faces the way out
+    pub fn teleport(&mut self, player: &mut Player) -> bool {
+        let here = self.cell(player.pos.x, player.pos.y);
+        if self.arrived_at == Some(here) {
+            return false;
+        }
+        self.arrived_at = None;
+        let Some(&target) = self.teleporters.get(&here) else {
+            return false;
+        };
+
+        let open = |angle: f32| {
+            let i = (target.0 as f32 + angle.cos().round()) as usize;
+            let j = (target.1 as f32 + angle.sin().round()) as usize;
+            self.maze.get(i).and_then(|row| row.get(j)).is_some_and(|tile| is_floor(*tile))
+        };
+        let facing = (player.a / (PI / 2.0)).round() * (PI / 2.0);
+        if !open(facing) {
+            if let Some(way_out) = (0..4).map(|n| n as f32 * PI / 2.0).find(|angle| open(*angle)) {
+                player.velocity = rotate_vec2(&player.velocity, way_out - player.a);
+                player.turn(way_out - player.a);
+            }
+        }
+
+        let pos = self.center(target);
+        player.set_pos(pos.x, pos.y);
+        self.arrived_at = Some(target);
+        true
+    }
+
+    // Where a point inside a portal comes out of the linked one, and how much
+    // a direction going through has to turn
+    pub fn portal_exit(&self, cell: (usize, usize), pos: Vec2) -> Option<(Vec2, f32)> {
+        let portal = self.portals.get(&cell)?;
+        let exit = self.portals.get(&portal.to)?;
+        let side = |portal: &Portal| Vec2::new(portal.facing.0 as f32, portal.facing.1 as f32);
+        let half = self.block_size as f32 / 2.0;
+        let entrance = self.center(cell) + side(portal) * half;
+        let way_out = self.center(portal.to) + side(exit) * half;
+        // Going in against one side means coming out along the other
+        let turn = side(exit).y.atan2(side(exit).x) + PI - side(portal).y.atan2(side(portal).x);
+        Some((way_out + rotate_vec2(&(pos - entrance), turn), turn))
+    }
+
+    // Moves the player out of the linked portal once they step into one,
+    // returns true when it happened
+    pub fn pass_portal(&self, player: &mut Player) -> bool {
+        let here = self.cell(player.pos.x, player.pos.y);
+        let Some((pos, turn)) = self.portal_exit(here, player.pos) else {
+            return false;
+        };
+        player.set_pos(pos.x, pos.y);
+        player.turn(turn);
+        player.velocity = rotate_vec2(&player.velocity, turn);
+        true
+    }
+
+    pub fn cell(&self, x: f32, y: f32) -> (usize, usize) {
+        ((x / self.block_size as f32) as usize, (y / self.block_size as f32) as usize)
+    }
+
+    // Checks a point against the door panel of a cell, giving where the panel was hit (0 to 1).
+    // `reach` is how far from the middle of the cell still counts as touching the panel
+    pub fn door_hit(&self, i: usize, j: usize, x: f32, y: f32, reach: f32) -> Option<f32> {
+        let door = self.doors.get(&(i, j))?;
+        let size = self.block_size as f32;
+        let (across, along) = if door.along_x {
+            (y - (j as f32 + 0.5) * size, x / size - i as f32)
+        } else {
+            (x - (i as f32 + 0.5) * size, y / size - j as f32)
+        };
+        // The panel slides along itself, leaving the opening behind
+        if across.abs() <= reach && along >= door.open {
+            Some(along - door.open)
+        } else {
+            None
+        }
+    }
+
+    // Opens or closes the door the player is facing or standing in,
+    // locked doors stay closed unless the player carries their key
+    pub fn interact(&mut self, player: &Player) {
+        let size = self.block_size as f32;
+        let ahead = self.cell(
+            player.pos.x + player.a.cos() * size * 0.8,
+            player.pos.y + player.a.sin() * size * 0.8,
+        );
+        let here = self.cell(player.pos.x, player.pos.y);
+        if is_secret(self.maze[ahead.0][ahead.1]) {
+            self.push(ahead, player);
+            return;
+        }
+        for cell in [ahead, here] {
+            if let Some(door) = self.doors.get_mut(&cell) {
+                // Don't close the door on top of the player
+                if door.target > 0.5 && cell == here {
+                    return;
+                }
+                if door.lock.is_some_and(|key| !player.inventory.contains(&key)) {
+                    return;
+                }
+                door.target = if door.target > 0.5 { 0.0 } else { 1.0 };
+                self.make_noise(cell, DOOR_NOISE);
+                return;
+            }
+        }
+    }
+
+    // Starts sliding a secret wall away from the player, up to two empty cells. It stops
+    // short of anyone standing in the way, and doesn't move if they're right behind it
+    fn push(&mut self, cell: (usize, usize), player: &Player) {
+        let a = player.a;
+        let direction = if a.cos().abs() >= a.sin().abs() {
+            (a.cos().signum() as i32, 0)
+        } else {
+            (0, a.sin().signum() as i32)
+        };
+        let step = |n: i32| ((cell.0 as i32 + direction.0 * n) as usize, (cell.1 as i32 + direction.1 * n) as usize);
+        let free = |to: (usize, usize)| {
+            self.maze.get(to.0).and_then(|row| row.get(to.1)) == Some(&' ')
+                && !self.items.iter().any(|item| self.cell(item.pos.x, item.pos.y) == to)
+                && !self.enemies.iter().any(|enemy| self.cell(enemy.pos.x, enemy.pos.y) == to)
+                && self.cell(player.pos.x, player.pos.y) != to
+        };
+        let cells = (1..=PUSH_CELLS).take_while(|n| free(step(*n))).count() as i32;
+        if cells == 0 {
+            return;
+        }
+        self.maze[cell.0][cell.1] = ' ';
+        self.push_walls.push(PushWall { from: cell, direction, cells, offset: 0.0 });
+        self.paths.clear();
+        self.stats.secrets_found += 1;
+    }
+
+    // Whether a point is inside one of the walls that are sliding
+    pub fn push_wall_hit(&self, x: f32, y: f32) -> bool {
+        let size = self.block_size as f32;
+        self.push_walls.iter().any(|wall| {
+            let xo = (wall.from.0 as f32 + wall.direction.0 as f32 * wall.offset) * size;
+            let yo = (wall.from.1 as f32 + wall.direction.1 as f32 * wall.offset) * size;
+            x >= xo && x < xo + size && y >= yo && y < yo + size
+        })
+    }
+
+    // Whether something walking can be at a point: floors, open doors, but not sliding walls
+    pub fn is_open(&self, x: f32, y: f32) -> bool {
+        if x < 0.0 || y < 0.0 || self.push_wall_hit(x, y) {
+            return false;
+        }
+        self.is_open_cell(self.cell(x, y))
+    }
+
+    // Whether a cell can be walked right now: floors and open doors
+    pub fn is_open_cell(&self, cell: (usize, usize)) -> bool {
+        match self.maze.get(cell.0).and_then(|row| row.get(cell.1)) {
+            Some(tile) if is_door(*tile) => self.doors.get(&cell).is_some_and(|door| door.open > 0.9),
+            Some(tile) => is_floor(*tile),
+            None => false,
+        }
+    }
+
+    pub fn make_noise(&mut self, cell: (usize, usize), loudness: f32) {
+        if loudness > 0.0 {
+            self.noises.push((cell, loudness));
+        }
+    }
+
+    // Moves the enemies, returns the health the player loses to them.
+    // Each enemy hears the loudest of this frame's sounds that reaches it
+    pub fn update_enemies(&mut self, player: &Player, delta_time: f32) -> f32 {
+        let noises: Vec<_> = std::mem::take(&mut self.noises)
+            .into_iter()
+            .map(|(cell, loudness)| (cell, spread(self, cell, loudness)))
+            .collect();
+        let mut enemies = std::mem::take(&mut self.enemies);
+        let mut paths = std::mem::take(&mut self.paths);
+        let damage = enemies
+            .iter_mut()
+            .map(|enemy| {
+                let here = self.cell(enemy.pos.x, enemy.pos.y);
+                let heard = noises
+                    .iter()
+                    .filter_map(|(cell, heard)| heard.get(&here).map(|loudness| (*cell, *loudness)))
+                    .max_by(|a, b| a.1.total_cmp(&b.1))
+                    .map(|(cell, _)| cell);
+                enemy.update(self, &mut paths, player, heard, delta_time)
+            })
+            .sum();
+        self.enemies = enemies;
+        self.paths = paths;
+        damage
+    }
+
+    // Health the floor under the player takes per second, jumping keeps them off it
+    pub fn damage(&self, player: &Player) -> f32 {
+        if !player.on_ground() {
+            return 0.0;
+        }
+        let here = self.cell(player.pos.x, player.pos.y);
+        hazard_damage(self.maze[here.0][here.1], self.clock)
+    }
+
+    // The state to save when the player stands on a checkpoint
+    pub fn checkpoint(&self, player: &Player) -> Option<Checkpoint> {
+        let here = self.cell(player.pos.x, player.pos.y);
+        if !is_checkpoint(self.maze[here.0][here.1]) {
+            return None;
+        }
+        Some(Checkpoint {
+            cell: here,
+            a: player.a,
+            inventory: player.inventory.clone(),
+            items: self.items.clone(),
+            maze: self.maze.clone(),
+            doors: self.doors.clone(),
+            push_walls: self.push_walls.clone(),
+            stats: self.stats,
+        })
+    }
+
+    // Puts the player back on a checkpoint, keeping what they carry
+    pub fn return_to(&self, player: &mut Player, checkpoint: &Checkpoint) {
+        let pos = self.center(checkpoint.cell);
+        player.set_pos(pos.x, pos.y);
+        player.a = checkpoint.a;
+        player.velocity = Vec2::new(0.0, 0.0);
+    }
+
+    // Starts again from a checkpoint, with the items, doors and secrets as they were when it was saved
+    pub fn restore(&mut self, player: &mut Player, checkpoint: &Checkpoint) {
+        self.return_to(player, checkpoint);
+        player.inventory = checkpoint.inventory.clone();
+        self.items = checkpoint.items.clone();
+        self.maze = checkpoint.maze.clone();
+        self.doors = checkpoint.doors.clone();
+        self.push_walls = checkpoint.push_walls.clone();
+        self.stats = checkpoint.stats;
+        self.paths.clear();
+    }
+
+    // Moves the items on the player's cell to the inventory
+    pub fn pick_up(&mut self, player: &mut Player) {
+        let here = self.cell(player.pos.x, player.pos.y);
+        let block_size = self.block_size as f32;
+        self.items.retain(|item| {
+            let cell = ((item.pos.x / block_size) as usize, (item.pos.y / block_size) as usize);
+            if cell == here {
+                player.inventory.push(item.kind);
+                false
+            } else {
+                true
+            }
+        });
+    }
+
+    pub fn update(&mut self, player: &Player, delta_time: f32) {
+        self.clock += delta_time;
+        let mut moved = false;
+        for door in self.doors.values_mut() {
+            let step = DOOR_SPEED * delta_time;
+            let was_open = door.open > 0.9;
+            if door.open < door.target {
+                door.open = (door.open + step).min(door.target);
+            } else {
+                door.open = (door.open - step).max(door.target);
+            }
+            moved |= was_open != (door.open > 0.9);
+        }
+
+        for wall in self.push_walls.iter_mut() {
+            wall.offset = (wall.offset + PUSH_SPEED * delta_time).min(wall.cells as f32);
+        }
+        // Walls that got to the end become regular walls again, one cell short
+        // for each cell someone walked into while it was sliding
+        let mut taken: Vec<(usize, usize)> = self.enemies.iter().map(|enemy| self.cell(enemy.pos.x, enemy.pos.y)).collect();
+        taken.push(self.cell(player.pos.x, player.pos.y));
+        let maze = &mut self.maze;
+        self.push_walls.retain(|wall| {
+            if wall.offset < wall.cells as f32 {
+                return true;
+            }
+            let at = |n: i32| {
+                ((wall.from.0 as i32 + wall.direction.0 * n) as usize, (wall.from.1 as i32 + wall.direction.1 * n) as usize)
+            };
+            let cells = (0..=wall.cells).rev().find(|n| !taken.contains(&at(*n))).unwrap_or(0);
+            let (i, j) = at(cells);
+            maze[i][j] = '|';
+            moved = true;
+            false
+        });
+        if moved {
+            self.paths.clear();
+        }
+    }
+}
+
+// The first side of a cell that opens to the floor, like (0, 1)
+pub fn open_side(maze: &[Vec<char>], cell: (usize, usize)) -> Option<(i32, i32)> {
+    [(1, 0), (-1, 0), (0, 1), (0, -1)].into_iter().find(|(di, dj)| {
+        let i = (cell.0 as i32 + di) as usize;
+        let j = (cell.1 as i32 + dj) as usize;
+        maze.get(i).and_then(|row| row.get(j)).is_some_and(|tile| is_floor(*tile))
+    })
+}
@@ -1,5 +1,8 @@
+use std::collections::{HashMap, HashSet};
 use std::fs::File;
 use std::io::{BufRead, BufReader};
+use crate::level::{is_door, is_floor, is_key, is_low_ceiling, is_portal, is_secret, is_see_through, is_teleporter, wall_height};
+use crate::pathfinding::DistanceField;
 
 pub fn load_maze(filename: &str) -> Vec<Vec<char>> {
     let file = File::open(filename).unwrap();
@@ -9,4 +12,98 @@ pub fn load_maze(filename: &str) -> Vec<Vec<char>> {
         .lines()
         .map(|line| line.unwrap().chars().collect())
         .collect()
-}
\ No newline at end of file
+}
+
+// Checks that the maze can be finished: it needs a start and a goal, the goal has to be
+// reachable (picking up keys on the way) without pushing secret walls, and every secret
+// wall needs an open side the player can push it from. Portals need a pair and a single open side
+pub fn validate_maze(maze: &[Vec<char>]) -> Result<(), String> {
+    let find = |tile: char| {
+        (0..maze.len()).flat_map(|i| (0..maze[i].len()).map(move |j| (i, j))).find(|(i, j)| maze[*i][*j] == tile)
+    };
+    let start = find('p').ok_or("The maze has no start ('p')")?;
+    let goal = find('g').ok_or("The maze has no goal ('g')")?;
+
+    // The view goes over low walls and through glass and grates, on the border it would leave the maze
+    for (i, row) in maze.iter().enumerate() {
+        for (j, tile) in row.iter().enumerate() {
+            let border = i == 0 || j == 0 || i + 1 == maze.len() || j + 1 == row.len();
+            if border && wall_height(*tile) < 1.0 {
+                return Err(format!("The low wall at ({}, {}) can't be on the border", i, j));
+            }
+            if border && is_see_through(*tile) {
+                return Err(format!("The glass or grate at ({}, {}) can't be on the border", i, j));
+            }
+        }
+    }
+
+    // Teleporters and portals both join two cells
+    let mut links: HashMap<(usize, usize), (usize, usize)> = HashMap::new();
+    let mut unpaired: HashMap<char, (usize, usize)> = HashMap::new();
+    for (i, row) in maze.iter().enumerate() {
+        for (j, tile) in row.iter().enumerate() {
+            if is_portal(*tile) {
+                let open = [(1, 0), (-1, 0), (0, 1), (0, -1)]
+                    .iter()
+                    .filter(|(di, dj)| {
+                        let cell = ((i as i32 + di) as usize, (j as i32 + dj) as usize);
+                        maze.get(cell.0).and_then(|row| row.get(cell.1)).is_some_and(|tile| is_floor(*tile) || is_key(*tile))
+                    })
+                    .count();
+                if open != 1 {
+                    return Err(format!("The portal at ({}, {}) needs exactly one open side", i, j));
+                }
+            }
+            if is_teleporter(*tile) || is_portal(*tile) {
+                if let Some(other) = unpaired.remove(tile) {
+                    links.insert(other, (i, j));
+                    links.insert((i, j), other);
+                } else {
+                    unpaired.insert(*tile, (i, j));
+                }
+            }
+        }
+    }
+    if let Some(tile) = unpaired.keys().next() {
+        return Err(format!("Teleporter or portal '{}' has no pair", tile));
+    }
+
+    // Keep exploring while new keys open more doors
+    let mut keys: Vec<char> = Vec::new();
+    let reached = loop {
+        let reached: HashSet<(usize, usize)> =
+            DistanceField::new(maze, start, &links, passable(&keys)).reached().copied().collect();
+        let found: Vec<char> = reached
+            .iter()
+            .map(|(i, j)| maze[*i][*j])
+            .filter(|tile| is_key(*tile) && !keys.contains(tile))
+            .collect();
+        if found.is_empty() {
+            break reached;
+        }
+        keys.extend(found);
+    };
+
+    if !reached.contains(&goal) {
+        return Err("The goal can't be reached from the start".to_string());
+    }
+    // A secret wall only needs a reachable cell next to it to be pushed
+    for (i, row) in maze.iter().enumerate() {
+        for (j, tile) in row.iter().enumerate() {
+            let pushable = [(1, 0), (-1, 0), (0, 1), (0, -1)]
+                .iter()
+                .any(|(di, dj)| reached.contains(&((i as i32 + di) as usize, (j as i32 + dj) as usize)));
+            if is_secret(*tile) && !pushable {
+                return Err(format!("The secret wall at ({}, {}) can't be reached", i, j));
+            }
+        }
+    }
+    Ok(())
+}
+
+// Cells that can be walked holding the given keys, secret walls count as walls
+fn passable(keys: &[char]) -> impl Fn((usize, usize), char) -> bool + '_ {
+    move |_, tile| {
+        is_floor(tile) || is_key(tile) || is_portal(tile) || is_low_ceiling(tile) || tile == 'D' || (is_door(tile) && keys.contains(&tile.to_ascii_lowercase()))
+    }
+}
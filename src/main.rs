@@ -2,11 +2,18 @@ use minifb::{Key, Window, WindowOptions};
 use nalgebra_glm::Vec2;
 use core::f32::consts::PI;
 use framebuffer::Framebuffer;
-use loader::load_maze;
-use player::{process_event, Player};
+use color::Color;
+use loader::{load_maze, validate_maze};
+use player::{process_event, Input, Player};
 use ray_caster::cast_ray;
 use std::time::{ Instant,Duration};
+use std::rc::Rc;
 use audio::AudioPlayer;
+use animation::Animation;
+use assets::AssetCache;
+use settings::Settings;
+use level::{is_low_ceiling, Checkpoint, Level, Stats};
+use bot::Bot;
 
 mod framebuffer;
 mod color;
@@ -16,59 +23,66 @@ mod ray_caster;
 mod fps;
 mod sprite_loader;
 mod audio;
+mod animation;
+mod assets;
+mod settings;
+mod level;
+mod enemy;
+mod pathfinding;
+mod noise;
+mod bot;
+
+// Seconds a hint arrow stays on screen
+const HINT_TIME: f32 = 8.0;
 
 struct Goal{
     pos: Vec2,
-    sprite: sprite_loader::Sprite,
+    animation: Animation,
 }
 
 impl Goal{
-    fn new(pos:Vec2, sprite:sprite_loader::Sprite) -> Self{
+    fn new(pos:Vec2, animation:Animation) -> Self{
         Goal{
             pos,
-            sprite,
+            animation,
         }
     }
 }
 
 fn draw_player_view(
     framebuffer: &mut Framebuffer,
-    maze: &Vec<Vec<char>>,
+    level: &Level,
     player: &mut Player,
-    block_size: usize,
     scale: usize,
     goal: &Goal
 ){
+    let block_size = level.block_size;
     framebuffer.clear();
     framebuffer.set_current_color(0x008dfc);
     sprite_loader::draw_block(framebuffer, player.pos.x as usize-block_size/12 ,player.pos.y as usize-block_size/12, block_size/6);
     framebuffer.set_current_color(0xffffff);
-    sprite_loader::render2d(framebuffer, maze, scale, player,false);
+    sprite_loader::render2d(framebuffer, level, scale, player,false);
     let num_rays = 3;
     
     for i in 0..num_rays{
         let current_ray = i as f32/ num_rays as f32;
         let a = player.a -(player.fov / 2.0) + (player.fov * current_ray);
-        cast_ray(framebuffer, maze, player, a, block_size, true, &goal);
+        cast_ray(framebuffer, level, player, a, true, &goal);
     }
 }
 
 fn draw_minimap(
     framebuffer: &mut Framebuffer,
-    maze: &Vec<Vec<char>>,
+    level: &Level,
     player: &mut Player,
-    block_size: usize,
     scale: usize,
 ){
+    let maze = &level.maze;
+    let block_size = level.block_size;
     let minimap_width = (maze[0].len() * scale / 4) - 6;
     let minimap_height = maze.len() * scale;
 
-    framebuffer.set_current_color(0x000000);
-    for x in 0..minimap_width {
-        for y in 0..minimap_height {
-            framebuffer.point(x, y);
-        }
-    }
+    fps::render_panel(framebuffer, 0, 0, minimap_width, minimap_height, Color::new(0, 0, 0).with_alpha(170));
 
     framebuffer.set_current_color(0x008dfc);
     sprite_loader::draw_block(
@@ -77,20 +91,56 @@ fn draw_minimap(
         (player.pos.y * scale as f32 / block_size as f32) as usize - block_size / 24,
         block_size / 12,
     );
+    // Which way the player faces
+    for step in 0..scale{
+        framebuffer.point(
+            (player.pos.x * scale as f32 / block_size as f32 + player.a.cos() * step as f32) as usize,
+            (player.pos.y * scale as f32 / block_size as f32 + player.a.sin() * step as f32) as usize,
+        );
+    }
 
     framebuffer.set_current_color(0xffffff);
-    sprite_loader::render2d(framebuffer, maze, scale, player, true);
+    sprite_loader::render2d(framebuffer, level, scale, player, true);
 }
 
-fn playing(screen: &mut usize){
-    let goal_name = "./src/sprites/prizes/sandwich.bmp";
-    let maze_name = "./src/mazes/maze1.txt";
+// Which sides have a wall right next to the player: front, back, left and right
+fn blocked(framebuffer: &mut Framebuffer, level: &Level, player: &Player, goal: &Goal) -> [bool; 4] {
+    [0.0, PI, -PI / 2.0, PI / 2.0].map(|turn| cast_ray(framebuffer, level, player, player.a + turn, false, goal).distance < 8.0)
+}
+
+// Moves the level on by a frame, everything but the player's input. Returns whether
+// the player was teleported and the health they lost
+fn advance(level: &mut Level, player: &mut Player, delta_time: f32) -> (bool, f32) {
+    level.update(player, delta_time);
+    level.pick_up(player);
+    level.pass_portal(player);
+    let here = level.cell(player.pos.x, player.pos.y);
+    player.update_height(delta_time, is_low_ceiling(level.maze[here.0][here.1]));
+    let teleported = level.teleport(player);
+    level.make_noise(here, player.noise());
+    let damage = level.damage(player) * delta_time + level.update_enemies(player, delta_time);
+    (teleported, damage)
+}
+
+// The title plays a demo of the bot when it's left alone (screen 4), it ends on any key
+fn playing(
+    maze_name: &str,
+    hints_left: &mut usize,
+    screen: &mut usize,
+    stats: &mut Stats,
+    checkpoint: &mut Option<Checkpoint>,
+    assets: &mut AssetCache,
+) -> Result<(), String>{
+    let goal_name = "./src/sprites/prizes/sandwich_spin.bmp";
     let audio_player = AudioPlayer::new("./src/audios/theme_song.mp3");
 
+    let mut settings = Settings::load("./src/settings.txt");
+
     let maze = load_maze(maze_name);
+    validate_maze(&maze)?;
     let mut goal = Goal::new(
         Vec2::new(0.0, 0.0),
-        sprite_loader::Sprite::new(goal_name));
+        Animation::from_sheet(assets.load(goal_name)?, &[0.1; 8]));
     let numbers = load_maze("./src/mazes/numbers.txt");
 
     let window_width = 600;
@@ -105,8 +155,14 @@ fn playing(screen: &mut usize){
 
     let mut player = Player::new(block_size);
     let frame_delay = Duration::from_millis(0);
+    let mut level = Level::new(maze, block_size);
 
-    sprite_loader::init_maze(&mut framebuffer, &maze, block_size, &mut player, &mut goal);
+    sprite_loader::init_maze(&mut framebuffer, &level, &mut player, &mut goal);
+    let demo = *screen == 4;
+    let mut bot = demo.then(Bot::default);
+    if let Some(saved) = checkpoint.as_ref().filter(|_| !demo) {
+        level.restore(&mut player, saved);
+    }
     audio_player.play();
 
     let mut window = Window::new(
@@ -119,16 +175,54 @@ fn playing(screen: &mut usize){
 
     let mut mode = "3D";
     let mut last_time = Instant::now();
-    let mut last_input = Instant::now();
     let mut fps_counter = 0;
     let mut fps_last = 10;
-    let wall_1 = sprite_loader::Sprite::new("./src/sprites/walls/wallTile1.bmp");
-    let wall_2 = sprite_loader::Sprite::new("./src/sprites/walls/wallTile2.bmp");
-    let wall_3 = sprite_loader::Sprite::new("./src/sprites/walls/wallTile3.bmp");
-    let sprites = [&wall_1, &wall_2, &wall_3];
+    let wall_atlas = assets.atlas(&[
+        "./src/sprites/walls/wallTile1.bmp",
+        "./src/sprites/walls/wallTile2.bmp",
+        "./src/sprites/walls/wallTile3.bmp",
+        "./src/sprites/walls/wallPanel.bmp",
+        "./src/sprites/walls/door.bmp",
+        "./src/sprites/walls/door_red.bmp",
+        "./src/sprites/walls/door_blue.bmp",
+        "./src/sprites/walls/door_yellow.bmp",
+        "./src/sprites/walls/glass.bmp",
+        "./src/sprites/walls/grate.bmp",
+        "./src/sprites/walls/mirror.bmp",
+    ])?;
+    let key_atlas = assets.atlas(&[
+        "./src/sprites/prizes/key_red.bmp",
+        "./src/sprites/prizes/key_blue.bmp",
+        "./src/sprites/prizes/key_yellow.bmp",
+    ])?;
+    let textures = sprite_loader::Textures {
+        walls: vec![
+            Animation::from_atlas(&wall_atlas, 0),
+            Animation::from_atlas(&wall_atlas, 1),
+            Animation::from_atlas(&wall_atlas, 2),
+            Animation::from_strip(Rc::clone(&wall_atlas.sheet), wall_atlas.regions[3], &[0.6, 0.1, 0.4, 0.1]),
+            Animation::from_atlas(&wall_atlas, 4),
+            Animation::from_atlas(&wall_atlas, 5),
+            Animation::from_atlas(&wall_atlas, 6),
+            Animation::from_atlas(&wall_atlas, 7),
+            Animation::from_atlas(&wall_atlas, 8),
+            Animation::from_atlas(&wall_atlas, 9),
+            Animation::from_atlas(&wall_atlas, 10),
+        ],
+        items: (0..3).map(|i| Animation::from_atlas(&key_atlas, i)).collect(),
+        teleporter: Animation::from_sheet(assets.load("./src/sprites/prizes/teleporter.bmp")?, &[0.15; 4]),
+        enemy: Animation::from_sheet(assets.load("./src/sprites/enemies/drone.bmp")?, &[0.15; 4]),
+    };
+    let mut flash = 0.0;
+    let mut hurt: f32 = 0.0;
+    // Seconds the hint arrow stays up, and the way it shows, worked out when it was asked for
+    let mut hint: f32 = 0.0;
+    let mut hint_path = Vec::new();
+    let start = Instant::now();
+    let mut last_frame = Instant::now();
     while window.is_open(){
         
-        if window.is_key_down(Key::Escape) {
+        if window.is_key_down(Key::Escape) || (demo && !window.get_keys().is_empty()) {
             break;
         }
 
@@ -136,40 +230,81 @@ fn playing(screen: &mut usize){
             mode = if mode == "2D" {"3D"} else {"2D"}
         }
 
+        if window.is_key_pressed(Key::F, minifb::KeyRepeat::No){
+            settings.filtering = !settings.filtering;
+        }
+
+        if window.is_key_pressed(Key::R, minifb::KeyRepeat::No){
+            if let Some(saved) = checkpoint {
+                level.return_to(&mut player, saved);
+            }
+        }
+        let delta_time = last_frame.elapsed().as_secs_f32();
+        last_frame = Instant::now();
+        if window.is_key_pressed(Key::H, minifb::KeyRepeat::No) && hint == 0.0 && *hints_left > 0 {
+            *hints_left -= 1;
+            hint = HINT_TIME;
+            hint_path = bot::plan(&level, &player);
+        }
+        hint = (hint - delta_time).max(0.0);
+        if hint == 0.0 {
+            hint_path.clear();
+        }
+        let input = match bot.as_mut() {
+            Some(bot) => bot.input(&level, &player, delta_time),
+            None => Input::read(&window, &mut player),
+        };
+        if input.interact {
+            level.interact(&player);
+        }
+        let (teleported, damage) = advance(&mut level, &mut player, delta_time);
+        if teleported {
+            flash = 1.0;
+            audio_player.play_tone(880.0, Duration::from_millis(150));
+        }
+        flash = (flash - delta_time * 3.0).max(0.0);
+
+        if let Some(saved) = level.checkpoint(&player).filter(|_| !demo) {
+            if checkpoint.as_ref().map(|old| old.cell) != Some(saved.cell) {
+                audio_player.play_tone(660.0, Duration::from_millis(120));
+            }
+            *checkpoint = Some(saved);
+        }
+
+        if damage > 0.0 {
+            player.health -= damage;
+            hurt = 0.4;
+        }
+        hurt = (hurt - delta_time * 2.0).max(0.0);
+        if player.health <= 0.0 {
+            *screen = if demo { 0 } else { 2 };
+            break;
+        }
+
         if mode == "2D"{ 
-            draw_player_view(&mut framebuffer, &maze, &mut player, block_size,block_size, &mut goal);
+            draw_player_view(&mut framebuffer, &level, &mut player, block_size, &mut goal);
         } else {
-            sprite_loader::render3d(&mut framebuffer, &maze, &mut player, block_size, &sprites, &mut goal);
-            draw_minimap(&mut framebuffer, &maze, &mut player, block_size, 8);
-
-            if last_input.elapsed() >= Duration::from_millis(16) {
-                let intersect_f = cast_ray(&mut framebuffer, &maze, &player, player.a, block_size, false, &goal);
-                let intersect_b = cast_ray(&mut framebuffer, &maze, &player, player.a + PI, block_size, false, &goal);
-                let intersect_l = cast_ray(&mut framebuffer, &maze, &player, player.a - PI / 2.0, block_size, false, &goal);
-                let intersect_r = cast_ray(&mut framebuffer, &maze, &player, player.a + PI / 2.0, block_size, false, &goal);
-                
-                let mut wall_f = false;
-                if intersect_f.distance < 8.0{
-                    wall_f = true;
-                }
-                let mut wall_b = false;
-                if intersect_b.distance < 8.0{
-                    wall_b = true;
-                }
-                let mut wall_l = false;
-                if intersect_l.distance < 8.0{
-                    wall_l = true;
-                }
-                let mut wall_r = false;
-                if intersect_r.distance < 8.0{
-                    wall_r = true;
-                }
-                process_event(&mut player, &window, wall_f, wall_b, wall_l, wall_r);
-                last_input = Instant::now();
+            sprite_loader::render3d(&mut framebuffer, &level, &mut player, &textures, &mut goal, start.elapsed().as_secs_f32(), &settings);
+            draw_minimap(&mut framebuffer, &level, &mut player, 8);
+            sprite_loader::render_inventory(&mut framebuffer, &player, &textures);
+            sprite_loader::render_health(&mut framebuffer, &player);
+            sprite_loader::render_stamina(&mut framebuffer, &player);
+            sprite_loader::render_compass(&mut framebuffer, &player, goal.pos, &numbers);
+            if settings.hints > 0 {
+                sprite_loader::render_hint(&mut framebuffer, &level, &player, *hints_left, &hint_path);
             }
+
+            let blocked = |player: &Player| blocked(&mut framebuffer, &level, player, &goal);
+            process_event(&mut player, &input, &settings, blocked, delta_time);
         }
 
         if player.win_condition{
+            if demo {
+                *screen = 0;
+                break;
+            }
+            stats.secrets_found += level.stats.secrets_found;
+            stats.secrets_total += level.stats.secrets_total;
             *screen= 3;
             break;
         }
@@ -180,6 +315,8 @@ fn playing(screen: &mut usize){
             fps_counter = 0;
             last_time = Instant::now();
         }
+        fps::render_flash(&mut framebuffer, Color::new(160, 240, 255), flash);
+        fps::render_flash(&mut framebuffer, Color::new(220, 20, 20), hurt);
         fps::render_fps(&mut framebuffer, &numbers, fps_last);
 
 
@@ -192,16 +329,123 @@ fn playing(screen: &mut usize){
             .unwrap();
         std::thread::sleep(frame_delay);
     }
+    // Closed without winning or dying
+    if *screen == 1 || *screen == 4 {
+        *screen = 0;
+    }
+    Ok(())
 }
 
-fn main() {
+// Every maze file, in the order they're played
+fn maze_names() -> Result<Vec<String>, String> {
+    let mut maze_names: Vec<String> = std::fs::read_dir("./src/mazes")
+        .map_err(|err| err.to_string())?
+        .filter_map(|entry| entry.ok()?.file_name().into_string().ok())
+        .filter(|name| name.starts_with("maze"))
+        .map(|name| format!("./src/mazes/{}", name))
+        .collect();
+    maze_names.sort();
+    if maze_names.is_empty() {
+        return Err("No mazes in ./src/mazes".to_string());
+    }
+    Ok(maze_names)
+}
+
+fn main() -> Result<(), String> {
     let mut screen: usize = 0;
+    let mut assets = AssetCache::new();
+    let mut stats = Stats::default();
+    let mut checkpoint = None;
 
-    sprite_loader::pre_play(&mut screen);
-    if screen!=0{
-        playing(&mut screen);
+    let settings = Settings::load("./src/settings.txt");
+    for (path, key) in &settings.color_keys {
+        assets.set_color_key(path, *key);
+    }
+
+    sprite_loader::pre_play(&mut screen, &mut assets)?;
+    // 1 is playing, 2 is the death screen, 3 the ending and 4 the demo the title
+    // plays when left alone, which goes back to the title when it ends
+    // The mazes are played in order, the demo always plays the first one.
+    // Hints are given per maze, dying doesn't bring them back
+    let maze_names = maze_names()?;
+    let hints = settings.hints;
+    let mut current = 0;
+    let mut hints_left = hints;
+    while screen==1 || screen==4{
+        let demo = screen==4;
+        let maze_name = if demo { &maze_names[0] } else { &maze_names[current] };
+        let mut demo_hints = 0;
+        let maze_hints = if demo { &mut demo_hints } else { &mut hints_left };
+        playing(maze_name, maze_hints, &mut screen, &mut stats, &mut checkpoint, &mut assets)?;
+        if screen==2{
+            sprite_loader::death_screen(&mut screen, &mut assets)?;
+        }
+        // Finishing a maze goes on to the next one, the ending comes after the last
+        if screen==3 && current + 1 < maze_names.len(){
+            current += 1;
+            checkpoint = None;
+            hints_left = hints;
+            screen = 1;
+        }
+        if demo{
+            sprite_loader::pre_play(&mut screen, &mut assets)?;
+        }
     }
     if screen==3{
-        sprite_loader::post_play();
+        sprite_loader::post_play(&mut assets, &stats)?;
+    }
+    Ok(())
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    // Most frames the bot gets to finish a maze, ten minutes at 60 frames per second
+    const MAX_FRAMES: usize = 36000;
+
+    // Plays a maze with the bot and no window, returns how many frames it took
+    fn play(maze_name: &str, assets: &mut AssetCache) -> Result<usize, String> {
+        let maze = load_maze(maze_name);
+        validate_maze(&maze)?;
+        let block_size = 600 / maze.len();
+        let mut framebuffer = Framebuffer::new(600, 600);
+        let settings = Settings::load("./src/settings.txt");
+        let mut goal = Goal::new(
+            Vec2::new(0.0, 0.0),
+            Animation::still(assets.load("./src/sprites/prizes/sandwich_spin.bmp")?));
+        let mut player = Player::new(block_size);
+        let mut level = Level::new(maze, block_size);
+        sprite_loader::init_maze(&mut framebuffer, &level, &mut player, &mut goal);
+        let mut bot = Bot::default();
+
+        let delta_time = 1.0 / 60.0;
+        for frame in 0..MAX_FRAMES {
+            let input = bot.input(&level, &player, delta_time);
+            if input.interact {
+                level.interact(&player);
+            }
+            let (_, damage) = advance(&mut level, &mut player, delta_time);
+            player.health -= damage;
+            if player.health <= 0.0 {
+                return Err(format!("The bot died in {} after {} frames", maze_name, frame));
+            }
+            let blocked = |player: &Player| blocked(&mut framebuffer, &level, player, &goal);
+            process_event(&mut player, &input, &settings, blocked, delta_time);
+
+            let here = level.cell(player.pos.x, player.pos.y);
+            if level.maze[here.0][here.1] == 'g' {
+                return Ok(frame);
+            }
+        }
+        Err(format!("The bot didn't finish {} in {} frames", maze_name, MAX_FRAMES))
+    }
+
+    #[test]
+    fn bot_finishes_every_maze() {
+        let mut assets = AssetCache::new();
+        for maze_name in maze_names().unwrap() {
+            play(&maze_name, &mut assets).unwrap_or_else(|err| panic!("{}", err));
+        }
     }
 }
@@ -0,0 +1,38 @@
+use std::collections::{HashMap, VecDeque};
+use crate::level::{is_door, Level};
+
+// Loudness is how many open cells a sound gets through before it dies out
+pub const WALK_NOISE: f32 = 2.0;
+pub const SPRINT_NOISE: f32 = 6.0;
+pub const DOOR_NOISE: f32 = 5.0;
+// What getting through a closed door costs
+const DOOR_MUFFLE: f32 = 3.0;
+
+// How loud a sound made on a cell is on every cell it reaches. It goes through floors
+// and open doors, closed doors muffle it and walls stop it
+pub fn spread(level: &Level, from: (usize, usize), loudness: f32) -> HashMap<(usize, usize), f32> {
+    let mut heard = HashMap::from([(from, loudness)]);
+    let mut queue = VecDeque::from([from]);
+    while let Some((i, j)) = queue.pop_front() {
+        let left = heard[&(i, j)];
+        for (di, dj) in [(1, 0), (-1, 0), (0, 1), (0, -1)] {
+            let next = ((i as i32 + di) as usize, (j as i32 + dj) as usize);
+            let Some(&tile) = level.maze.get(next.0).and_then(|row| row.get(next.1)) else {
+                continue;
+            };
+            let cost = if level.is_open_cell(next) {
+                1.0
+            } else if is_door(tile) {
+                DOOR_MUFFLE
+            } else {
+                continue;
+            };
+            let louder = left - cost;
+            if louder > 0.0 && !heard.get(&next).is_some_and(|old| *old >= louder) {
+                heard.insert(next, louder);
+                queue.push_back(next);
+            }
+        }
+    }
+    heard
+}
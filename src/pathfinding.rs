@@ -0,0 +1,284 @@
+use std::cmp::Reverse;
+use std::collections::{BinaryHeap, HashMap, VecDeque};
+
+// Paths go between cells of the maze grid, moving to the four neighbours of a cell
+// or through a link (a teleporter or portal pair). `passable` tells which cells can be walked
+
+type Cell = (usize, usize);
+
+// Every step there is from a cell, links included
+fn neighbours<'a>(cell: Cell, links: &'a HashMap<Cell, Cell>) -> impl Iterator<Item = Cell> + 'a {
+    [(1, 0), (-1, 0), (0, 1), (0, -1)]
+        .into_iter()
+        .filter_map(move |(di, dj): (i32, i32)| {
+            let i = cell.0.checked_add_signed(di as isize)?;
+            let j = cell.1.checked_add_signed(dj as isize)?;
+            Some((i, j))
+        })
+        .chain(links.get(&cell).copied())
+}
+
+fn walkable(maze: &[Vec<char>], cell: Cell, passable: &impl Fn(Cell, char) -> bool) -> bool {
+    maze.get(cell.0).and_then(|row| row.get(cell.1)).is_some_and(|tile| passable(cell, *tile))
+}
+
+fn manhattan(a: Cell, b: Cell) -> usize {
+    a.0.abs_diff(b.0) + a.1.abs_diff(b.1)
+}
+
+// Shortest path with A*, both ends included. None when `to` can't be reached
+pub fn find_path(
+    maze: &[Vec<char>],
+    from: Cell,
+    to: Cell,
+    links: &HashMap<Cell, Cell>,
+    passable: impl Fn(Cell, char) -> bool,
+) -> Option<Vec<Cell>> {
+    // Never more than the real distance, a link may be a shortcut
+    let estimate = |cell: Cell| {
+        links
+            .iter()
+            .map(|(entry, exit)| manhattan(cell, *entry) + 1 + manhattan(*exit, to))
+            .fold(manhattan(cell, to), usize::min)
+    };
+    let mut came_from: HashMap<Cell, Cell> = HashMap::new();
+    let mut cost: HashMap<Cell, usize> = HashMap::from([(from, 0)]);
+    let mut open = BinaryHeap::from([Reverse((estimate(from), 0, from))]);
+
+    while let Some(Reverse((_, steps, cell))) = open.pop() {
+        if cell == to {
+            let mut path = vec![to];
+            while let Some(previous) = came_from.get(path.last().unwrap()) {
+                path.push(*previous);
+            }
+            path.reverse();
+            return Some(path);
+        }
+        if steps > cost[&cell] {
+            continue;
+        }
+        for next in neighbours(cell, links) {
+            if !walkable(maze, next, &passable) || cost.get(&next).is_some_and(|known| *known <= steps + 1) {
+                continue;
+            }
+            cost.insert(next, steps + 1);
+            came_from.insert(next, cell);
+            open.push(Reverse((steps + 1 + estimate(next), steps + 1, next)));
+        }
+    }
+    None
+}
+
+// Distance from every reachable cell to one target, with the way to go from each of them
+pub struct DistanceField {
+    distances: HashMap<Cell, usize>,
+    toward: HashMap<Cell, Cell>,
+}
+
+impl DistanceField {
+    // Spreads out from the target. The target counts as walkable even if `passable` says no
+    pub fn new(
+        maze: &[Vec<char>],
+        target: Cell,
+        links: &HashMap<Cell, Cell>,
+        passable: impl Fn(Cell, char) -> bool,
+    ) -> Self {
+        let mut distances = HashMap::from([(target, 0)]);
+        let mut toward = HashMap::new();
+        let mut queue = VecDeque::from([target]);
+        while let Some(cell) = queue.pop_front() {
+            let distance = distances[&cell];
+            for next in neighbours(cell, links) {
+                if walkable(maze, next, &passable) && !distances.contains_key(&next) {
+                    distances.insert(next, distance + 1);
+                    toward.insert(next, cell);
+                    queue.push_back(next);
+                }
+            }
+        }
+        DistanceField { distances, toward }
+    }
+
+    pub fn distance(&self, cell: Cell) -> Option<usize> {
+        self.distances.get(&cell).copied()
+    }
+
+    // The cell to go to next on the way to the target
+    pub fn next(&self, cell: Cell) -> Option<Cell> {
+        self.toward.get(&cell).copied()
+    }
+
+    pub fn reached(&self) -> impl Iterator<Item = &Cell> {
+        self.distances.keys()
+    }
+}
+
+// Distance fields kept around by target, they have to be cleared when the maze changes
+#[derive(Default)]
+pub struct PathCache {
+    fields: HashMap<Cell, DistanceField>,
+}
+
+// Targets kept before starting over, a moving target leaves a trail of them
+const CACHE_SIZE: usize = 64;
+
+impl PathCache {
+    pub fn clear(&mut self) {
+        self.fields.clear();
+    }
+
+    pub fn toward(
+        &mut self,
+        maze: &[Vec<char>],
+        target: Cell,
+        links: &HashMap<Cell, Cell>,
+        passable: impl Fn(Cell, char) -> bool,
+    ) -> &DistanceField {
+        if self.fields.len() >= CACHE_SIZE && !self.fields.contains_key(&target) {
+            self.fields.clear();
+        }
+        self.fields
+            .entry(target)
+            .or_insert_with(|| DistanceField::new(maze, target, links, passable))
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use core::f32::consts::FRAC_PI_2;
+    use crate::level::Level;
+    use crate::player::Player;
+
+    fn grid(rows: &[&str]) -> Vec<Vec<char>> {
+        rows.iter().map(|row| row.chars().collect()).collect()
+    }
+
+    fn open(_: Cell, tile: char) -> bool {
+        tile != '#'
+    }
+
+    // Every step goes to a neighbour or through a link, never into a wall
+    fn assert_walkable(maze: &[Vec<char>], path: &[Cell], links: &HashMap<Cell, Cell>) {
+        for pair in path.windows(2) {
+            assert!(manhattan(pair[0], pair[1]) == 1 || links.get(&pair[0]) == Some(&pair[1]));
+            assert_ne!(maze[pair[1].0][pair[1].1], '#');
+        }
+    }
+
+    #[test]
+    fn straight_corridor() {
+        let maze = grid(&["######", "#    #", "######"]);
+        let path = find_path(&maze, (1, 1), (1, 4), &HashMap::new(), open);
+        assert_eq!(path, Some(vec![(1, 1), (1, 2), (1, 3), (1, 4)]));
+    }
+
+    #[test]
+    fn detour_around_walls() {
+        let maze = grid(&[
+            "#######",
+            "#  #  #",
+            "#  #  #",
+            "#     #",
+            "#######",
+        ]);
+        let path = find_path(&maze, (1, 1), (1, 5), &HashMap::new(), open).unwrap();
+        assert_eq!(path.first(), Some(&(1, 1)));
+        assert_eq!(path.last(), Some(&(1, 5)));
+        assert_eq!(path.len(), 9);
+        assert_walkable(&maze, &path, &HashMap::new());
+    }
+
+    #[test]
+    fn unreachable_goal() {
+        let maze = grid(&["#######", "#  #  #", "#  #  #", "#######"]);
+        assert_eq!(find_path(&maze, (1, 1), (1, 5), &HashMap::new(), open), None);
+        assert_eq!(DistanceField::new(&maze, (1, 5), &HashMap::new(), open).distance((1, 1)), None);
+    }
+
+    #[test]
+    fn path_through_a_link() {
+        let maze = grid(&["#########", "#       #", "#########", "#   #   #", "#########"]);
+        // A shortcut along the corridor, and the only way between the two rooms below
+        let links = HashMap::from([
+            ((1, 1), (1, 7)),
+            ((1, 7), (1, 1)),
+            ((3, 3), (3, 5)),
+            ((3, 5), (3, 3)),
+        ]);
+        assert_eq!(find_path(&maze, (1, 1), (1, 7), &links, open), Some(vec![(1, 1), (1, 7)]));
+        let path = find_path(&maze, (3, 1), (3, 7), &links, open).unwrap();
+        assert_eq!(path.len(), 6);
+        assert_walkable(&maze, &path, &links);
+    }
+
+    #[test]
+    fn distances_match_paths() {
+        let maze = grid(&[
+            "########",
+            "#  #   #",
+            "# ## # #",
+            "#    # #",
+            "########",
+        ]);
+        let links = HashMap::from([((3, 1), (1, 6)), ((1, 6), (3, 1))]);
+        let target = (3, 6);
+        let field = DistanceField::new(&maze, target, &links, open);
+        for &cell in field.reached() {
+            let path = find_path(&maze, cell, target, &links, open).unwrap();
+            assert_eq!(field.distance(cell), Some(path.len() - 1), "from {:?}", cell);
+            // Following `next` gets one step closer each time
+            if let Some(next) = field.next(cell) {
+                assert_eq!(field.distance(next), Some(path.len() - 2));
+            }
+        }
+        assert_eq!(field.next(target), None);
+    }
+
+    // Distance from the start cell to `target` as the enemies see it, through the level's cache
+    fn cached_distance(level: &mut Level, target: Cell) -> Option<usize> {
+        let mut paths = std::mem::take(&mut level.paths);
+        let distance = paths
+            .toward(&level.maze, target, &HashMap::new(), |cell, _| level.is_open_cell(cell))
+            .distance((1, 1));
+        level.paths = paths;
+        distance
+    }
+
+    fn player_at(level: &Level, cell: Cell) -> Player {
+        let mut player = Player::new(level.block_size);
+        let pos = level.center(cell);
+        player.set_pos(pos.x, pos.y);
+        player.a = FRAC_PI_2;
+        player
+    }
+
+    #[test]
+    fn cache_cleared_when_a_door_opens() {
+        let mut level = Level::new(grid(&["+-+-+-+", "|p D  |", "+-+-+-+"]), 50);
+        assert_eq!(cached_distance(&mut level, (1, 5)), None);
+
+        let player = player_at(&level, (1, 2));
+        level.interact(&player);
+        for _ in 0..60 {
+            level.update(&player, 1.0 / 30.0);
+        }
+        assert!(level.paths.fields.is_empty());
+        assert_eq!(cached_distance(&mut level, (1, 5)), Some(4));
+    }
+
+    #[test]
+    fn cache_cleared_when_a_push_wall_stops() {
+        let mut level = Level::new(grid(&["+-+-+-+-+", "|p %    |", "+-+-+-+-+"]), 50);
+        assert_eq!(cached_distance(&mut level, (1, 4)), None);
+
+        let player = player_at(&level, (1, 2));
+        level.interact(&player);
+        for _ in 0..120 {
+            level.update(&player, 1.0 / 30.0);
+        }
+        assert!(level.push_walls.is_empty());
+        assert!(level.paths.fields.is_empty());
+        assert_eq!(cached_distance(&mut level, (1, 4)), Some(3));
+    }
+}
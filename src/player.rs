@@ -1,18 +1,67 @@
 use nalgebra_glm::Vec2;
 use core::f32::consts::PI;
 use minifb::{Key, Window};
+use crate::noise::{SPRINT_NOISE, WALK_NOISE};
+use crate::settings::Settings;
 
 
 const ROTATION_SPEED: f32 = PI/50.0;
+// How far the horizon can move from the middle of the screen, in pixels
+const MAX_PITCH: f32 = 150.0;
+// Eye heights are in walls, standing the eye is at the middle of one
+const EYE_HEIGHT: f32 = 0.5;
+const CROUCH_HEIGHT: f32 = 0.25;
+const CROUCH_SPEED: f32 = 1.5;
+const JUMP_SPEED: f32 = 1.6;
+const GRAVITY: f32 = 5.0;
+// Input used to move a fixed step every 16 ms, speeds are per second now
+const TICKS_PER_SECOND: f32 = 60.0;
+// Longest step taken at once, a tick. Slow frames are split in several equal steps so the
+// player keeps its speed, walls are looked for again before each of them
+const MAX_STEP_TIME: f32 = 1.0 / TICKS_PER_SECOND;
+pub const MAX_HEALTH: f32 = 100.0;
+// Sprinting speed compared to walking, and stamina used and recovered per second (it goes from 0 to 1)
+const SPRINT_FACTOR: f32 = 1.5;
+const STAMINA_DRAIN: f32 = 0.35;
+const STAMINA_REGEN: f32 = 0.2;
+// After running out, sprinting waits until this much stamina is back
+const STAMINA_RECOVERED: f32 = 0.3;
+// Farthest a single step goes, less than the 8 px the player is kept from walls
+const MAX_STEP: f32 = 7.5;
+// Head bob height in walls and distance walked per step in blocks
+const BOB_HEIGHT: f32 = 0.02;
+const STRIDE: f32 = 0.6;
 
 pub struct Player{
     pub pos:nalgebra_glm::Vec2,
+    // Where the player faces, kept in [0, 2π)
     pub a: f32,
     pub fov: f32,
+    // Horizon shift for looking up and down, positive looks up
+    pub pitch: f32,
+    pub crouching: bool,
+    // Eye height of the current stance, it moves slowly between standing and crouching
+    stance: f32,
+    // Height gained by jumping
+    lift: f32,
+    vertical_speed: f32,
+    // World space, pixels per second
+    pub velocity: Vec2,
+    pub sprinting: bool,
+    pub stamina: f32,
+    exhausted: bool,
+    pub health: f32,
+    // Distance walked, in blocks, for the head bob
+    walked: f32,
+    bob: f32,
     pub win_condition: bool,
+    // Collected items, like keys
+    pub inventory: Vec<char>,
     move_speed: f32,
+    block_size: f32,
     rotation_speed: f32,
     last_mouse_x: Option<f32>,
+    last_mouse_y: Option<f32>,
 }
 
 impl Player{
@@ -21,10 +70,25 @@ impl Player{
             pos: Vec2::new(0.0, 0.0),
             a: PI/3.0,
             fov: PI/3.0,
+            pitch: 0.0,
+            crouching: false,
+            stance: EYE_HEIGHT,
+            lift: 0.0,
+            vertical_speed: 0.0,
+            velocity: Vec2::new(0.0, 0.0),
+            sprinting: false,
+            stamina: 1.0,
+            exhausted: false,
+            health: MAX_HEALTH,
+            walked: 0.0,
+            bob: 0.0,
             win_condition: false,
+            inventory: Vec::new(),
             move_speed: block_size as f32/10.0,
+            block_size: block_size as f32,
             rotation_speed: ROTATION_SPEED,
             last_mouse_x: None,
+            last_mouse_y: None,
         }
     }
 
@@ -33,45 +97,199 @@ impl Player{
         self.pos.y = y;
     }
     pub fn rotate(&mut self, delta_x: f32){ // true - Right, false - left
-        self.a += delta_x * self.rotation_speed;
+        self.turn(delta_x * self.rotation_speed);
     }
-    pub fn inc_pos(&mut self, direction: Vec2){
-        let delta_x = self.move_speed*direction.x;
-        let delta_y = self.move_speed*direction.y;
+    pub fn turn(&mut self, angle: f32){
+        self.a = (self.a + angle).rem_euclid(2.0 * PI);
+        // Rounding can give back 2π for tiny negative angles
+        if self.a >= 2.0 * PI {
+            self.a = 0.0;
+        }
+    }
+    // Mouse motion that turns the player by an angle
+    pub fn turn_for(&self, angle: f32) -> f32{
+        angle / self.rotation_speed
+    }
+    pub fn look(&mut self, delta_y: f32){
+        self.pitch = (self.pitch - delta_y).clamp(-MAX_PITCH, MAX_PITCH);
+    }
+    // Only from the ground and standing up
+    pub fn jump(&mut self){
+        if self.lift == 0.0 && self.stance == EYE_HEIGHT {
+            self.vertical_speed = JUMP_SPEED;
+        }
+    }
+    // Moves the eye for jumps and crouching, a low ceiling keeps the player crouched
+    pub fn update_height(&mut self, delta_time: f32, low_ceiling: bool){
+        self.lift += self.vertical_speed * delta_time;
+        self.vertical_speed -= GRAVITY * delta_time;
+        if self.lift <= 0.0 {
+            self.lift = 0.0;
+            self.vertical_speed = 0.0;
+        }
+
+        let stance = if self.crouching || low_ceiling { CROUCH_HEIGHT } else { EYE_HEIGHT };
+        let step = CROUCH_SPEED * delta_time;
+        self.stance = if self.stance < stance {
+            (self.stance + step).min(stance)
+        } else {
+            (self.stance - step).max(stance)
+        };
+    }
+    // Out of breath after running out of stamina, no sprinting until it recovers
+    pub fn exhausted(&self) -> bool{
+        self.exhausted
+    }
+    pub fn on_ground(&self) -> bool{
+        self.lift == 0.0
+    }
+    pub fn eye_height(&self) -> f32{
+        self.stance + self.lift + self.bob
+    }
+    // How loud the player's steps are, crouching or in the air they make none
+    pub fn noise(&self) -> f32{
+        let speed = self.velocity.magnitude() / (self.move_speed * TICKS_PER_SECOND);
+        if self.crouching || !self.on_ground() || speed < 0.1 {
+            0.0
+        } else if speed > 1.05 {
+            SPRINT_NOISE
+        } else {
+            WALK_NOISE * speed
+        }
+    }
+    // Speeds up towards `direction` (forward and right of where the player faces) and slows
+    // down when nothing is pressed, walls `blocked` finds next to the player (front, back, left, right)
+    // stop the motion towards them
+    pub fn inc_pos(&mut self, direction: Vec2, mut blocked: impl FnMut(&Player) -> [bool; 4], delta_time: f32, settings: &Settings){
+        let steps = (delta_time / MAX_STEP_TIME).ceil().max(1.0);
+        for _ in 0..steps as usize {
+            let walls = blocked(self);
+            self.step(direction, walls, delta_time / steps, settings);
+        }
+    }
+
+    fn step(&mut self, direction: Vec2, blocked: [bool; 4], delta_time: f32, settings: &Settings){
+        let walk_speed = self.move_speed * TICKS_PER_SECOND;
+        let moving = direction.magnitude() != 0.0;
+        let sprint = self.sprinting && moving && !self.exhausted;
+        if sprint {
+            self.stamina = (self.stamina - STAMINA_DRAIN * delta_time).max(0.0);
+            self.exhausted = self.stamina == 0.0;
+        } else {
+            self.stamina = (self.stamina + STAMINA_REGEN * delta_time).min(1.0);
+            self.exhausted = self.exhausted && self.stamina < STAMINA_RECOVERED;
+        }
+        let top_speed = if sprint { walk_speed * SPRINT_FACTOR } else { walk_speed };
+        let (cos, sin) = (self.a.cos(), self.a.sin());
+        let mut forward = self.velocity.x * cos + self.velocity.y * sin;
+        let mut right = -self.velocity.x * sin + self.velocity.y * cos;
+
+        // Zero acceleration or friction means changing speed at once
+        let rate = if moving { settings.acceleration } else { settings.friction };
+        let approach = |speed: f32, target: f32| {
+            let step = rate * top_speed * delta_time;
+            if rate <= 0.0 {
+                target
+            } else if speed < target {
+                (speed + step).min(target)
+            } else {
+                (speed - step).max(target)
+            }
+        };
+        forward = approach(forward, direction.x * top_speed);
+        right = approach(right, direction.y * top_speed);
 
-        self.pos.x += delta_x * self.a.cos() - delta_y * self.a.sin();
-        self.pos.y += delta_x * self.a.sin() + delta_y * self.a.cos();
+        if (blocked[0] && forward > 0.0) || (blocked[1] && forward < 0.0) {
+            forward = 0.0;
+        }
+        if (blocked[2] && right < 0.0) || (blocked[3] && right > 0.0) {
+            right = 0.0;
+        }
+        self.velocity = Vec2::new(forward * cos - right * sin, forward * sin + right * cos);
+        let mut step = self.velocity * delta_time;
+        if step.magnitude() > MAX_STEP {
+            step = step.normalize() * MAX_STEP;
+        }
+        self.pos += step;
 
+        let speed = self.velocity.magnitude();
+        self.walked += speed * delta_time / self.block_size;
+        self.bob = if settings.head_bob && self.lift == 0.0 {
+            (self.walked / STRIDE * PI).sin().abs() * BOB_HEIGHT * (speed / walk_speed).min(SPRINT_FACTOR)
+        } else {
+            0.0
+        };
     }
 }
 
-pub fn process_event(player: &mut Player, window: &Window, wall_f: bool, wall_b: bool, wall_l: bool, wall_r: bool){
+// What the player is told to do on a frame, from the keyboard and mouse or from the bot
+#[derive(Default)]
+pub struct Input {
+    // Forward and right of where the player faces
+    pub direction: Vec2,
+    pub jump: bool,
+    pub crouch: bool,
+    pub sprint: bool,
+    // Opening doors and pushing walls
+    pub interact: bool,
+    // Mouse motion in pixels, sideways turns and up and down looks
+    pub turn: f32,
+    pub look: f32,
+}
 
-    let mut direction = Vec2::new(0.0, 0.0);
+impl Input {
+    pub fn read(window: &Window, player: &mut Player) -> Self {
+        let mut input = Input::default();
+        if window.is_key_down(Key::W) {
+            input.direction.x += 1.0;
+        }
+        if window.is_key_down(Key::A) {
+            input.direction.y -= 1.0;
+        }
+        if window.is_key_down(Key::S) {
+            input.direction.x -= 1.0;
+        }
+        if window.is_key_down(Key::D) {
+            input.direction.y += 1.0;
+        }
+        input.jump = window.is_key_down(Key::Space);
+        input.crouch = window.is_key_down(Key::C) || window.is_key_down(Key::LeftCtrl);
+        input.sprint = window.is_key_down(Key::LeftShift) || window.is_key_down(Key::RightShift);
+        input.interact = window.is_key_pressed(Key::E, minifb::KeyRepeat::No);
 
-    if window.is_key_down(Key::W) && !wall_f {
-        direction.x += 1.0;
-    }
-    if window.is_key_down(Key::A) && !wall_l {
-        direction.y -= 1.0;
-    }
-    if window.is_key_down(Key::S) && !wall_b {
-        direction.x -= 1.0;
+        if let Some(mouse_pos) = window.get_mouse_pos(minifb::MouseMode::Pass) {
+            if let Some(last_x) = player.last_mouse_x {
+                input.turn = mouse_pos.0 - last_x;
+            }
+            if let Some(last_y) = player.last_mouse_y {
+                input.look = mouse_pos.1 - last_y;
+            }
+            player.last_mouse_x = Some(mouse_pos.0);
+            player.last_mouse_y = Some(mouse_pos.1);
+        }
+        input
     }
-    if window.is_key_down(Key::D) && !wall_r {
-        direction.y += 1.0;
+}
+
+// `blocked` tells which sides have a wall right next to the player: front, back, left and right.
+// Doors and walls are left to the level, see `Input::interact`
+pub fn process_event(player: &mut Player, input: &Input, settings: &Settings, blocked: impl FnMut(&Player) -> [bool; 4], delta_time: f32){
+    if input.jump {
+        player.jump();
     }
+    player.crouching = input.crouch;
+    player.sprinting = input.sprint;
 
+    let mut direction = input.direction;
     if direction.magnitude() != 0.0 {
         direction = direction.normalize();
-        player.inc_pos(direction);
     }
+    player.inc_pos(direction, blocked, delta_time, settings);
 
-    if let Some(mouse_pos) = window.get_mouse_pos(minifb::MouseMode::Pass) {
-        if let Some(last_x) = player.last_mouse_x {
-            let delta_x = mouse_pos.0 - last_x;
-            player.rotate(delta_x);
-        }
-        player.last_mouse_x = Some(mouse_pos.0);
+    player.rotate(input.turn);
+    if settings.pitch {
+        player.look(input.look);
+    } else {
+        player.pitch = 0.0;
     }
-}
\ No newline at end of file
+}
@@ -1,6 +1,12 @@
 use crate::framebuffer::Framebuffer;
+use crate::level::{is_door, is_floor, is_low_ceiling, is_mirror, is_see_through, wall_base, wall_height, Level};
 use crate::player::Player;
 use crate::Goal;
+use nalgebra_glm::{rotate_vec2, Vec2};
+
+// Times a ray can bounce off mirrors or go through portals before they're drawn as plain walls
+const MAX_BOUNCES: usize = 4;
+
 pub struct Intersect {
     pub distance: f32,
     pub impact: char,
@@ -9,51 +15,187 @@ pub struct Intersect {
     pub d_to_goal: f32,
 }
 
+// First thing the ray runs into, low and see-through walls included. Portals are
+// gone through, so they don't stop the player
 pub fn cast_ray(
     framebuffer: &mut Framebuffer,
-    maze: &Vec<Vec<char>>,
+    level: &Level,
     player: &Player,
     a: f32,
-    block_size: usize,
     draw_line: bool,
     goal: &Goal,
 )-> Intersect{
+    let framebuffer = draw_line.then_some(framebuffer);
+    let mut hits = march(framebuffer, level, player.pos, player.eye_height(), a, Some(goal), false, MAX_BOUNCES);
+    hits.remove(0)
+}
+
+// Every wall the ray runs into, nearest first, going past low walls, glass and grates
+// and bouncing off mirrors or going through portals until it reaches one that hides everything behind it.
+// Towers far enough behind a regular wall still show above it, so it goes on past those while they might
+pub fn cast_ray_layers(
+    level: &Level,
+    player: &Player,
+    a: f32,
+    goal: &Goal,
+)-> Vec<Intersect>{
+    march(None, level, player.pos, player.eye_height(), a, Some(goal), true, MAX_BOUNCES)
+}
+
+// Whether `to` can be seen from `from`, for eyes `eye` blocks above the floor at `to`.
+// Glass and grates don't hide anything and low walls only hide what's below their top.
+// Mirrors and portals block it, the ray would no longer be heading for `to` past them
+pub fn line_of_sight(level: &Level, from: Vec2, to: Vec2, eye: f32) -> bool {
+    let delta = to - from;
+    march(None, level, from, eye, delta.y.atan2(delta.x), None, true, 0)
+        .iter()
+        .take_while(|hit| hit.distance < delta.magnitude())
+        .all(|hit| {
+            let base = wall_base(hit.impact);
+            is_see_through(hit.impact) || eye < base || eye >= base + wall_height(hit.impact)
+        })
+}
+
+// Steps a ray from `from`, drawing it on the framebuffer when there's one.
+// It bounces off mirrors and goes through portals up to `max_bounces` times
+#[allow(clippy::too_many_arguments)]
+fn march(
+    mut framebuffer: Option<&mut Framebuffer>,
+    level: &Level,
+    from: Vec2,
+    eye: f32,
+    a: f32,
+    goal: Option<&Goal>,
+    see_past: bool,
+    max_bounces: usize,
+)-> Vec<Intersect>{
+    let maze = &level.maze;
+    let block_size = level.block_size;
+    let draw_line = framebuffer.is_some();
+    let step = if draw_line { 10.0 } else { 1.0 };
+    // Distance along the whole path, bounces included
     let mut d = 0.0;
+    // The ray starts again from the mirror after each bounce
+    let mut origin = (from.x, from.y);
+    let mut direction = (a.cos(), a.sin());
+    let mut t = 0.0;
+    let mut bounces = 0;
     let mut in_goal = false;
     let mut d_to_goal = 0.0;
+    let mut hits = Vec::new();
+    // Cell of the last wall looked past, so it's counted once
+    let mut passing: Option<(usize, usize)> = None;
+    let mut last = (origin, (usize::MAX, usize::MAX));
+    // Past a wall that hides the floor, only a taller wall closer than `reach` can still show above it
+    let mut reach = f32::INFINITY;
+    let mut hidden = false;
     loop{
-        let cos = d*a.cos();
-        let sin = d*a.sin();
-        let x = (player.pos.x + cos) as usize;
-        let y = (player.pos.y + sin) as usize;
+        if d > reach {
+            return hits;
+        }
+        let px = origin.0 + t*direction.0;
+        let py = origin.1 + t*direction.1;
+        // The casts below turn negative coordinates into 0, those are outside too
+        let inside = px >= 0.0 && py >= 0.0;
+        let x = px as usize;
+        let y = py as usize;
 
         let i = x/block_size;
         let j = y/block_size;
 
-        if (maze[i][j] != ' ') & (maze[i][j] != 'p'){
-            if maze[i][j] == 'g'{
-                if (x) as usize == (goal.pos.x) as usize{
-                    if (y) as usize == (goal.pos.y) as usize{
-                        in_goal= true;
-                        d_to_goal = d;
-                    }
-                }
+        let Some(&tile) = maze.get(i).and_then(|row| row.get(j)).filter(|_| inside) else {
+            // Left the maze, only possible after looking past a wall on the border
+            hits.push(Intersect{ distance: d, impact: ' ', texture_index: 0.0, in_goal, d_to_goal });
+            return hits;
+        };
 
-            } else{
-                return Intersect{
-                    distance: d,
-                    impact: maze[i][j],
-                    texture_index: ((x+y)%block_size) as f32/block_size as f32,
-                    in_goal,
-                    d_to_goal,
-                };
+        // Walking into a portal carries on from the linked one
+        if bounces < max_bounces {
+            if let Some((exit, turn)) = level.portal_exit((i, j), Vec2::new(px, py)) {
+                let turned = rotate_vec2(&Vec2::new(direction.0, direction.1), turn);
+                origin = (exit.x, exit.y);
+                direction = (turned.x, turned.y);
+                t = 0.0;
+                bounces += 1;
+                continue;
             }
         }
-        if draw_line{
+
+        let hit = if level.push_wall_hit(px, py){
+            Some(Intersect{
+                distance: d,
+                impact: '%',
+                texture_index: ((x+y)%block_size) as f32/block_size as f32,
+                in_goal,
+                d_to_goal,
+            })
+        } else if tile == 'g'{
+            if !hidden && goal.is_some_and(|goal| ((x) == (goal.pos.x) as usize) & ((y) == (goal.pos.y) as usize)){
+                in_goal= true;
+                d_to_goal = d;
+            }
+            None
+        } else if is_door(tile){
+            level.door_hit(i, j, px, py, step / 2.0).map(|texture_index| Intersect{
+                distance: d,
+                impact: tile,
+                texture_index,
+                in_goal,
+                d_to_goal,
+            })
+        } else if is_low_ceiling(tile) && !see_past && eye < wall_base(tile){
+            // Crouched low enough to crawl under it
+            None
+        } else if !is_floor(tile){
+            Some(Intersect{
+                distance: d,
+                impact: tile,
+                texture_index: ((x+y)%block_size) as f32/block_size as f32,
+                in_goal,
+                d_to_goal,
+            })
+        } else {
+            None
+        };
+
+        if let Some(hit) = hit {
+            if see_past && is_mirror(hit.impact) && bounces < max_bounces {
+                // Bounce off the side that was crossed to get in
+                let (from, cell) = last;
+                if cell.0 != i {
+                    direction.0 = -direction.0;
+                }
+                if cell.1 != j {
+                    direction.1 = -direction.1;
+                }
+                origin = from;
+                t = 0.0;
+                bounces += 1;
+                hits.push(hit);
+                continue;
+            }
+            let height = wall_height(hit.impact);
+            if !see_past || (height >= level.tallest && !is_see_through(hit.impact)) {
+                hits.push(hit);
+                return hits;
+            }
+            if height >= 1.0 && !is_see_through(hit.impact) && passing != Some((i, j)) {
+                // Something as tall as `tallest` shows above it while it's less than this far
+                hidden = true;
+                if height > eye {
+                    reach = reach.min(d * (level.tallest - eye) / (height - eye));
+                }
+            }
+            if passing != Some((i, j)) {
+                passing = Some((i, j));
+                hits.push(hit);
+            }
+        }
+        if let Some(framebuffer) = framebuffer.as_deref_mut(){
             framebuffer.point(x, y);
-            d+=10.0;
-        }else{
-            d+=1.0;
         }
+        last = ((px, py), (i, j));
+        t+=step;
+        d+=step;
     }
-}
\ No newline at end of file
+}
@@ -0,0 +1,74 @@
+use std::fs::File;
+use std::io::{BufRead, BufReader};
+
+pub struct Settings {
+    // Mipmaps and bilinear filtering on walls, off keeps the crisp retro look
+    pub filtering: bool,
+    // Looking up and down with the mouse, off keeps the horizon in the middle
+    pub pitch: bool,
+    // Top speeds gained per second while moving and lost per second when stopping, 0 is instant
+    pub acceleration: f32,
+    pub friction: f32,
+    // The camera goes up and down with each step
+    pub head_bob: bool,
+    // Hint arrows the player can ask for on each level, 0 turns them off
+    pub hints: usize,
+    // Color made transparent in each image that has one, for images without alpha
+    pub color_keys: Vec<(String, u32)>,
+}
+
+impl Default for Settings {
+    fn default() -> Self {
+        Settings {
+            filtering: false,
+            pitch: true,
+            acceleration: 8.0,
+            friction: 10.0,
+            head_bob: true,
+            hints: 3,
+            color_keys: Vec::new(),
+        }
+    }
+}
+
+impl Settings {
+    // Reads "name = value" lines, missing file or unknown names keep the defaults.
+    // Color keys go one per image as "color_key path = rrggbb"
+    pub fn load(filename: &str) -> Self {
+        let mut settings = Settings::default();
+        let file = match File::open(filename) {
+            Ok(file) => file,
+            Err(_) => return settings,
+        };
+
+        for line in BufReader::new(file).lines().map_while(Result::ok) {
+            let Some((name, value)) = line.split_once('=') else {
+                continue;
+            };
+            let value = value.trim();
+            match name.trim() {
+                "filtering" => settings.filtering = parse_flag(value, settings.filtering),
+                "pitch" => settings.pitch = parse_flag(value, settings.pitch),
+                "acceleration" => settings.acceleration = value.parse().unwrap_or(settings.acceleration),
+                "friction" => settings.friction = value.parse().unwrap_or(settings.friction),
+                "head_bob" => settings.head_bob = parse_flag(value, settings.head_bob),
+                "hints" => settings.hints = value.parse().unwrap_or(settings.hints),
+                name => {
+                    let path = name.strip_prefix("color_key").map(str::trim);
+                    if let (Some(path), Ok(key)) = (path, u32::from_str_radix(value, 16)) {
+                        settings.color_keys.push((path.to_string(), key));
+                    }
+                },
+            }
+        }
+        settings
+    }
+}
+
+fn parse_flag(value: &str, current: bool) -> bool {
+    match value {
+        "on" | "true" | "1" => true,
+        "off" | "false" | "0" => false,
+        _ => current,
+    }
+}
@@ -1,107 +1,206 @@
-use std::fs::File;
-use std::io::BufReader;
+use std::io::Cursor;
 use minifb::{Key, Window, WindowOptions};
-use std::time::Duration;
+use std::time::{Duration, Instant};
 use crate::color::Color;
 use bmp::{from_reader, Pixel};
 use crate::framebuffer::Framebuffer;
-use crate::player::Player;
+use crate::player::{Player, MAX_HEALTH};
 use crate::Goal;
-use crate::ray_caster::cast_ray;
+use crate::ray_caster::cast_ray_layers;
 use crate::audio::AudioPlayer;
-
+use crate::animation::{Animation, Frame};
+use crate::fps;
+use nalgebra_glm::Vec2;
+use core::f32::consts::PI;
+use crate::assets::{AssetCache, Region};
+use crate::settings::Settings;
+use crate::level::{is_live, is_mirror, is_see_through, wall_base, wall_height, Door, Level, Stats};
+use crate::loader::load_maze;
+use crate::bot::heading;
+
+// Seconds the title waits without a key pressed before playing the demo
+const DEMO_DELAY: u64 = 10;
+
+// Pixels are stored column by column, so a whole column can be borrowed
 pub struct Sprite{
     pub buffer: Vec<Color>,
     pub height: usize,
     pub width: usize,
+    // Smaller copies, each one half the size of the previous
+    pub mips: Vec<Sprite>,
 }
 
 impl Sprite{
-    pub fn render_screen(framebuffer: &mut Framebuffer, sprite: &Sprite) {
-        for x in 0..framebuffer.width - 1 {
-            // Transforming x to sprite coordinates
-            let trans_x = ((x as f32) / framebuffer.width as f32) * (sprite.width) as f32;
-            let current_line = sprite.get_line(trans_x as usize);
-            for y in 0..framebuffer.height - 1 {
-                // Transforming y to sprite coordinates
-                let trans_y = ((y as f32) / framebuffer.height as f32)* sprite.height as f32;
-                let color = Color::to_hex(&current_line[trans_y as usize]);
-                framebuffer.set_current_color(color);
-                framebuffer.point(x as usize, y);
-            }
-        }
+    pub fn column(&self, x: usize) -> &[Color]{
+        &self.buffer[x*self.height..(x+1)*self.height]
     }
-    
-    pub fn get_line(&self, x:usize)->Vec<Color>{
-        let mut line_buffer: Vec<Color> = Vec::new();
-        for y in 0..self.height{
-            line_buffer.push(self.buffer[x+(y*self.width)]);
+
+    // u and v go from 0 to 1 across the region
+    pub fn sample_region(&self, region: &Region, u: f32, v: f32) -> Color{
+        let x = ((u * region.width as f32) as usize).min(region.width - 1);
+        let y = ((v * region.height as f32) as usize).min(region.height - 1);
+        self.column(region.x + x)[region.y + y]
+    }
+
+    pub fn sample_bilinear(&self, region: &Region, u: f32, v: f32) -> Color{
+        let x = (u * region.width as f32 - 0.5).clamp(0.0, (region.width - 1) as f32);
+        let y = (v * region.height as f32 - 0.5).clamp(0.0, (region.height - 1) as f32);
+        let (x0, y0) = (x as usize, y as usize);
+        let x1 = (x0 + 1).min(region.width - 1);
+        let y1 = (y0 + 1).min(region.height - 1);
+        let (fx, fy) = (x.fract(), y.fract());
+
+        let left = self.column(region.x + x0);
+        let right = self.column(region.x + x1);
+        let top = Color::lerp(left[region.y + y0], right[region.y + y0], fx);
+        let bottom = Color::lerp(left[region.y + y1], right[region.y + y1], fx);
+        Color::lerp(top, bottom, fy)
+    }
+
+    // Texture at a mipmap level, 0 is the sprite itself
+    pub fn level(&self, lod: usize) -> &Sprite{
+        if lod == 0 || self.mips.is_empty() {
+            self
+        } else {
+            &self.mips[(lod - 1).min(self.mips.len() - 1)]
         }
-        return line_buffer;
     }
 
-    pub fn new(file_path: &str) -> Self{
-        let mut buffer: Vec<Color>  = Vec::new();
-        let mut height = 0;
-        let mut width = 0;
-        match read_bmp_to_framebuffer(file_path) {
-            Ok(sprite) => {
-                buffer=sprite;
+    pub fn build_mipmaps(&mut self){
+        let mut mips: Vec<Sprite> = Vec::new();
+        loop {
+            let previous = mips.last().unwrap_or(self);
+            if previous.width <= 1 && previous.height <= 1 {
+                break;
             }
-            Err(e) => eprintln!("Failed to read BMP file: {}", e),
-        }
-        match get_dimentions(file_path) {
-            Ok(dim) => {
-                height = dim.0;
-                width = dim.1;
+            let width = (previous.width / 2).max(1);
+            let height = (previous.height / 2).max(1);
+            let mut buffer = Vec::with_capacity(width * height);
+            for x in 0..width {
+                let left = previous.column((2 * x).min(previous.width - 1));
+                let right = previous.column((2 * x + 1).min(previous.width - 1));
+                for y in 0..height {
+                    let y0 = (2 * y).min(previous.height - 1);
+                    let y1 = (2 * y + 1).min(previous.height - 1);
+                    let top = Color::lerp(left[y0], right[y0], 0.5);
+                    let bottom = Color::lerp(left[y1], right[y1], 0.5);
+                    buffer.push(Color::lerp(top, bottom, 0.5));
+                }
             }
-            Err(e) => eprintln!("Failed to read BMP file: {}", e),
+            mips.push(Sprite{ buffer, height, width, mips: Vec::new() });
         }
+        self.mips = mips;
+    }
 
-        Sprite{
-            buffer,
-            height,
-            width,
+    // Decodes a BMP file, failing if it can't be opened or read.
+    // 32 bit images keep their alpha channel, the rest are opaque
+    pub fn load(file_path: &str) -> Result<Self, String>{
+        let data = std::fs::read(file_path).map_err(|e| format!("{}: {}", file_path, e))?;
+        if data.len() > 30 && u16::from_le_bytes([data[28], data[29]]) == 32 {
+            return read_bmp32(&data).map_err(|e| format!("{}: {}", file_path, e));
         }
-    }
-}
-pub fn read_bmp_to_framebuffer(file_path: &str) -> Result<Vec<Color>, String>{
-    let file = File::open(file_path).map_err(|e| e.to_string())?;
-    let mut reader = BufReader::new(file);
 
-    // Load the BMP image
+        // Load the BMP image
+
+        let bmp_image = from_reader(&mut Cursor::new(data)).map_err(|e| format!("{}: {}", file_path, e))?;
 
-    let bmp_image = from_reader(&mut reader).map_err(|e| e.to_string())?;
+        let height = bmp_image.get_height() as usize;
+        let width = bmp_image.get_width() as usize;
+        let mut buffer: Vec<Color> = vec![Color::new(0, 0, 0); width * height];
 
-    let mut framebuffer: Vec<Color> = Vec::new();
+        for (x, y) in bmp_image.coordinates() {
+            let pixel: Pixel = bmp_image.get_pixel(x, y);
+            let color_value: u32 = ((pixel.r as u32) << 16) | ((pixel.g as u32) << 8) | (pixel.b as u32);
+            buffer[x as usize * height + y as usize] = Color::from_hex(color_value);
+        }
 
-    for (x, y) in bmp_image.coordinates() {
-        let pixel: Pixel = bmp_image.get_pixel(x, y);
-        let color_value: u32 = ((pixel.r as u32) << 16) | ((pixel.g as u32) << 8) | (pixel.b as u32);
-        framebuffer.push(Color::from_hex(color_value));
+        Ok(Sprite{
+            buffer,
+            height,
+            width,
+            mips: Vec::new(),
+        })
     }
-    Ok(framebuffer)
-}
 
-pub fn get_dimentions(file_path: &str) -> Result<(usize,usize), String>{
-    let file = File::open(file_path).map_err(|e| e.to_string())?;
-    let mut reader = BufReader::new(file);
+    // Every pixel of the given color becomes transparent
+    pub fn apply_color_key(&mut self, key: u32){
+        for pixel in self.buffer.iter_mut() {
+            if pixel.to_hex() == key {
+                *pixel = pixel.with_alpha(0);
+            }
+        }
+    }
+}
 
-    // Load the BMP image
+// The bmp crate doesn't read 32 bit images. Channels are placed by the masks in the header
+// when there are some (BI_BITFIELDS), BGRA otherwise. Images with no alpha at all are opaque
+fn read_bmp32(data: &[u8]) -> Result<Sprite, String>{
+    let read_u32 = |at: usize| u32::from_le_bytes([data[at], data[at + 1], data[at + 2], data[at + 3]]);
+    if data.len() < 54 {
+        return Err("BMP header is too short".to_string());
+    }
+    let offset = read_u32(10) as usize;
+    let header_size = read_u32(14);
+    let width = (read_u32(18) as i32).unsigned_abs() as usize;
+    let raw_height = read_u32(22) as i32;
+    let height = raw_height.unsigned_abs() as usize;
+    let compression = read_u32(30);
+    // Red, green, blue and alpha. The alpha mask is only there in the newer headers
+    // or with BI_ALPHABITFIELDS
+    let masks = match compression {
+        0 => [0x00ff0000, 0x0000ff00, 0x000000ff, 0xff000000],
+        3 | 6 if data.len() >= 70 => {
+            let alpha = if header_size >= 56 || compression == 6 { read_u32(66) } else { 0 };
+            [read_u32(54), read_u32(58), read_u32(62), alpha]
+        },
+        _ => return Err("Unsupported BMP compression".to_string()),
+    };
+    if data.len() < offset + width * height * 4 {
+        return Err("BMP pixel data is too short".to_string());
+    }
+    // Scales a channel to 0..=255, None when the mask is empty
+    let channel = |pixel: u32, mask: u32| {
+        (mask != 0).then(|| {
+            let max = mask >> mask.trailing_zeros();
+            (((pixel & mask) >> mask.trailing_zeros()) * 255 / max) as u8
+        })
+    };
+
+    let mut buffer: Vec<Color> = vec![Color::new(0, 0, 0); width * height];
+    for row in 0..height {
+        // Rows are stored bottom to top unless the height is negative
+        let y = if raw_height > 0 { height - 1 - row } else { row };
+        for x in 0..width {
+            let pixel = read_u32(offset + (row * width + x) * 4);
+            buffer[x * height + y] = Color {
+                r: channel(pixel, masks[0]).unwrap_or(0),
+                g: channel(pixel, masks[1]).unwrap_or(0),
+                b: channel(pixel, masks[2]).unwrap_or(0),
+                a: channel(pixel, masks[3]).unwrap_or(255),
+            };
+        }
+    }
+    // Editors often leave the fourth byte at 0 when they don't store alpha
+    if buffer.iter().all(|color| color.a == 0) {
+        buffer.iter_mut().for_each(|color| color.a = 255);
+    }
 
-    let bmp_image = from_reader(&mut reader).map_err(|e| e.to_string())?;
-    let height = bmp_image.get_height();
-    let width = bmp_image.get_width();
-    Ok((height as usize,width as usize))
+    Ok(Sprite{
+        buffer,
+        height,
+        width,
+        mips: Vec::new(),
+    })
 }
 
 pub fn init_maze(
     framebuffer: &mut Framebuffer, 
-    maze: &Vec<Vec<char>>,
-    block_size: usize,
+    level: &Level,
     player: &mut Player,
     goal: &mut Goal,
 ){
+    let maze = &level.maze;
+    let block_size = level.block_size;
 
     for row in 0..maze.len(){
         for col in 0..maze[row].len(){
@@ -116,7 +215,9 @@ pub fn init_maze(
                 match maze[row][col] {
                     'g' =>(),
                     ' ' => (),
-                    'p' =>(),
+                    'p' | 'e' | 'w' =>(),
+                    '0'..='9' =>(),
+                    'D' | 'R' | 'B' | 'Y' =>(),
                     _ => {
                         draw_block(framebuffer, row*block_size, col*block_size, block_size);
                     },
@@ -128,11 +229,12 @@ pub fn init_maze(
 
 pub fn render2d(
     framebuffer: &mut Framebuffer, 
-    maze: &Vec<Vec<char>>,
+    level: &Level,
     block_size: usize,
     player: &mut Player,
     minimaze: bool
 ){
+    let maze = &level.maze;
     for row in 0..maze.len(){
         for col in 0..maze[row].len(){
                 match maze[row][col] {
@@ -144,7 +246,37 @@ pub fn render2d(
                              block_size/2);
                     },
                     ' ' => (),
-                    'p' =>(),
+                    'p' | 'e' | 'w' =>(),
+                    '0'..='9' =>(),
+                    '^' | '~' | '!' => {
+                        let color = floor_color(maze[row][col], 0.0, 0.0, level.clock).unwrap_or(Color::new(0, 0, 0));
+                        framebuffer.set_current_color(color.with_alpha(255).to_hex());
+                        draw_block(framebuffer, row*block_size, col*block_size, block_size);
+                    },
+                    'c' => {
+                        framebuffer.set_current_color(0x46e68c);
+                        draw_block(framebuffer,
+                             ((row as f32+0.25)*block_size as f32) as usize,
+                             ((col as f32+0.25)*block_size as f32) as usize,
+                             block_size/2);
+                    },
+                    '_' | ':' => {
+                        framebuffer.set_current_color(0x808080);
+                        draw_block(framebuffer, row*block_size, col*block_size, block_size);
+                    },
+                    '=' | '*' => {
+                        framebuffer.set_current_color(0x8cc8dc);
+                        draw_block(framebuffer, row*block_size, col*block_size, block_size);
+                    },
+                    'M' => {
+                        framebuffer.set_current_color(0xc8dcf0);
+                        draw_block(framebuffer, row*block_size, col*block_size, block_size);
+                    },
+                    'D' | 'R' | 'B' | 'Y' => {
+                        let door = &level.doors[&(row, col)];
+                        framebuffer.set_current_color(key_color(door.lock.unwrap_or(' ')));
+                        draw_door(framebuffer, door, row, col, block_size);
+                    },
                     _ => {
                         draw_block(framebuffer, row*block_size, col*block_size, block_size);
                     },
@@ -152,6 +284,52 @@ pub fn render2d(
                 framebuffer.set_current_color(0xffffff);
         }
     }
+    for wall in &level.push_walls{
+        let xo = (wall.from.0 as f32 + wall.direction.0 as f32 * wall.offset) * block_size as f32;
+        let yo = (wall.from.1 as f32 + wall.direction.1 as f32 * wall.offset) * block_size as f32;
+        draw_block(framebuffer, xo as usize, yo as usize, block_size);
+    }
+    // Teleporters, with a line joining each pair
+    for (from, to) in &level.teleporters{
+        framebuffer.set_current_color(0x50e6ff);
+        let marker = (block_size / 2).max(1);
+        draw_block(framebuffer,
+            from.0 * block_size + block_size / 4,
+            from.1 * block_size + block_size / 4,
+            marker);
+        if from < to {
+            framebuffer.line(
+                from.0 * block_size + block_size / 2, from.1 * block_size + block_size / 2,
+                to.0 * block_size + block_size / 2, to.1 * block_size + block_size / 2);
+        }
+    }
+    // Portals, also joined to their pair
+    for (from, to) in level.portals.iter().map(|(from, portal)| (from, &portal.to)){
+        framebuffer.set_current_color(0xb45aff);
+        draw_block(framebuffer, from.0 * block_size, from.1 * block_size, block_size);
+        if from < to {
+            framebuffer.line(
+                from.0 * block_size + block_size / 2, from.1 * block_size + block_size / 2,
+                to.0 * block_size + block_size / 2, to.1 * block_size + block_size / 2);
+        }
+    }
+    for item in &level.items{
+        framebuffer.set_current_color(key_color(item.kind));
+        let item_size = (block_size / 3).max(1);
+        draw_block(framebuffer,
+            (item.pos.x * block_size as f32 / level.block_size as f32) as usize - item_size / 2,
+            (item.pos.y * block_size as f32 / level.block_size as f32) as usize - item_size / 2,
+            item_size);
+    }
+    framebuffer.set_current_color(0xff3c32);
+    for enemy in &level.enemies{
+        let enemy_size = (block_size / 3).max(1);
+        draw_block(framebuffer,
+            (enemy.pos.x * block_size as f32 / level.block_size as f32) as usize - enemy_size / 2,
+            (enemy.pos.y * block_size as f32 / level.block_size as f32) as usize - enemy_size / 2,
+            enemy_size);
+    }
+    framebuffer.set_current_color(0xffffff);
     if !minimaze{
         if (maze[(player.pos.x/block_size as f32) as usize][(player.pos.y/block_size as f32) as usize])=='g'{
             player.win_condition=true;
@@ -167,84 +345,355 @@ pub fn draw_block(framebuffer: &mut Framebuffer, xo: usize, yo: usize, block_siz
     }
 }
 
+// Thin bar across the middle of the cell, shortened as the door slides open
+fn draw_door(framebuffer: &mut Framebuffer, door: &Door, row: usize, col: usize, block_size: usize){
+    let thickness = (block_size / 6).max(1);
+    let start = (door.open * block_size as f32) as usize;
+    let middle = block_size / 2 - thickness / 2;
+    for along in start..block_size{
+        for across in 0..thickness{
+            if door.along_x {
+                framebuffer.point(row*block_size + along, col*block_size + middle + across);
+            } else {
+                framebuffer.point(row*block_size + middle + across, col*block_size + along);
+            }
+        }
+    }
+}
+
+// Textures used while playing, indexed with wall_index and item_index
+pub struct Textures {
+    pub walls: Vec<Animation>,
+    pub items: Vec<Animation>,
+    pub teleporter: Animation,
+    pub enemy: Animation,
+}
+
 pub fn render3d(
     framebuffer: &mut Framebuffer,
-    maze: &Vec<Vec<char>>,
+    level: &Level,
     player: &mut Player,
-    block_size: usize,
-    sprites: &[&Sprite],
+    textures: &Textures,
     goal: &mut Goal,
+    time: f32,
+    settings: &Settings,
 ) {
+    let block_size = level.block_size;
     let num_rays = framebuffer.width;
     let hh = (framebuffer.height / 2) as f32;
+    // Where the floor meets the ceiling far away, moved by looking up and down
+    let horizon = hh + player.pitch;
+    let eye = player.eye_height();
     let mut try_sprite = false;
     let mut sprite_center = 0;
     let mut sprite_distance = 0.0;
     let background_color = Color::from_hex(0x323638);
+    // Distance of what was drawn on each pixel, for billboards to hide behind walls
+    let mut depth = vec![f32::MAX; framebuffer.width * framebuffer.height];
     for i in 0..num_rays {
         let current_ray = i as f32 / num_rays as f32;
         let a = player.a - (player.fov / 2.0) + (player.fov * current_ray);
-        let intersect = cast_ray(framebuffer, maze, player, a, block_size, false, goal);
+        let hits = cast_ray_layers(level, player, a, goal);
 
-        if intersect.in_goal{
+        if let Some(intersect) = hits.iter().find(|hit| hit.in_goal){
             try_sprite = true;
             sprite_center = i;
             sprite_distance = intersect.d_to_goal;
         }
-        let mut sprite_index = 2;
-        if intersect.impact=='+'{
-            sprite_index = 0;
-        } else if intersect.impact=='-'{
-            sprite_index = 1;
-        }
-
-        let d_to_wall = if intersect.distance > 10.0 {
-            intersect.distance
-        } else {
-            10.0
-        };
-        let d_to_plane: f32 = block_size as f32;
-
-        let current_line = sprites[sprite_index].get_line((intersect.texture_index * sprites[sprite_index].width as f32) as usize);
-
-        let stake_height = ((hh + block_size as f32) as f32 / d_to_wall) * d_to_plane;
-        let stake_top = (hh + (stake_height / 2.0)) as usize;
-        let stake_bottom = (hh - (stake_height / 2.0)) as usize;
 
         for y in 0..framebuffer.height {
-            if (y > stake_bottom) & (y < stake_top) {
-                let trans_y =
-                    (sprites[sprite_index].height as f32) * (y as f32 - hh + (stake_height / 2.0)) / stake_height;
-                framebuffer.set_current_color(Color::to_hex(&current_line[trans_y as usize]));
-                framebuffer.point(i, y);
-            } else if y <= stake_bottom {
-                framebuffer.set_current_color(Color::to_hex(&(background_color*(1.5-(y as f32/hh)))));
+            if (y as f32) < horizon {
+                framebuffer.set_current_color(Color::to_hex(&(background_color*(0.5+(horizon-y as f32)/hh))));
             } else {
-                framebuffer.set_current_color(Color::to_hex(&(background_color*(-0.5+(y as f32/hh)))));
+                let floor = background_color*(0.5+(y as f32-horizon)/hh);
+                // Distance to the floor seen on this pixel, to show hazards and checkpoints on it
+                let distance = (hh + block_size as f32) * block_size as f32 * eye / (y as f32 - horizon).max(0.5);
+                let x = player.pos.x + distance * a.cos();
+                let z = player.pos.y + distance * a.sin();
+                let (cell_x, cell_z) = (x / block_size as f32, z / block_size as f32);
+                let tile = level.maze.get(cell_x as usize).and_then(|row| row.get(cell_z as usize));
+                let color = match tile.and_then(|tile| floor_color(*tile, cell_x.fract(), cell_z.fract(), level.clock)) {
+                    Some(color) if x >= 0.0 && z >= 0.0 => color.over(floor),
+                    _ => floor,
+                };
+                framebuffer.set_current_color(Color::to_hex(&color));
             }
             framebuffer.point(i, y);
         }
+
+        // Farthest first, so low and see-through walls and mirrors end up in front of what they don't hide
+        for intersect in hits.iter().rev() {
+            if intersect.impact == ' ' {
+                continue;
+            }
+            let wall = &textures.walls[wall_index(intersect.impact)];
+            let texture = wall.frame(time);
+
+            let d_to_wall = if intersect.distance > 10.0 {
+                intersect.distance
+            } else {
+                10.0
+            };
+            let d_to_plane: f32 = block_size as f32;
+
+            // Walls are placed around the eye height, taller ones repeat the texture upwards
+            let stake_height = ((hh + block_size as f32) / d_to_wall) * d_to_plane;
+            let wall_bottom = horizon + stake_height * (eye - wall_base(intersect.impact));
+            let wall_top = wall_bottom - stake_height * wall_height(intersect.impact);
+            let start_y = wall_top.max(0.0) as usize;
+            let end_y = (wall_bottom.max(0.0) as usize).min(framebuffer.height);
+
+            for y in start_y..end_y {
+                let trans_y = ((y as f32 - wall_top) / stake_height).fract();
+                let color = if settings.filtering {
+                    texture.sample_filtered(intersect.texture_index, trans_y, stake_height)
+                } else {
+                    texture.sample(intersect.texture_index, trans_y)
+                };
+                if is_see_through(intersect.impact) || is_mirror(intersect.impact) {
+                    // Drawn over what's behind, only the solid parts hide billboards.
+                    // Mirrors tint their reflection and hide whatever is really behind them
+                    framebuffer.blend_point(i, y, color);
+                    if color.a < 255 && !is_mirror(intersect.impact) {
+                        continue;
+                    }
+                } else {
+                    framebuffer.set_current_color(Color::to_hex(&color));
+                    framebuffer.point(i, y);
+                }
+                depth[y * framebuffer.width + i] = intersect.distance;
+            }
+        }
+    }
+    for cell in level.teleporters.keys() {
+        let texture = textures.teleporter.frame(time);
+        draw_billboard(framebuffer, player, block_size, &depth, level.center(*cell), 0.8, texture);
+    }
+    for item in &level.items {
+        let texture = textures.items[item_index(item.kind)].frame(time);
+        draw_billboard(framebuffer, player, block_size, &depth, item.pos, 0.3, texture);
+    }
+    for enemy in &level.enemies {
+        draw_billboard(framebuffer, player, block_size, &depth, enemy.pos, 0.6, textures.enemy.frame(time));
     }
     if try_sprite & (sprite_distance>10.0){
-        draw_sprite(framebuffer, block_size, goal, sprite_distance, sprite_center)
+        draw_sprite(framebuffer, player, block_size, goal, sprite_distance, sprite_center, time)
     } else if try_sprite & (sprite_distance<10.0){
         player.win_condition=true;
     }
     framebuffer.set_current_color(0xffffff);
 }
 
+// Index of the wall animation used for each maze character
+pub fn wall_index(impact: char) -> usize{
+    match impact {
+        '+' | 'T' => 0,
+        '-' | '_' => 1,
+        '#' => 3,
+        'D' => 4,
+        'R' => 5,
+        'B' => 6,
+        'Y' => 7,
+        '=' => 8,
+        '*' => 9,
+        'M' => 10,
+        _ => 2,
+    }
+}
+
+pub fn item_index(kind: char) -> usize{
+    match kind {
+        'b' => 1,
+        'y' => 2,
+        _ => 0,
+    }
+}
+
+pub fn key_color(key: char) -> u32{
+    match key {
+        'r' => 0xdc3228,
+        'b' => 0x325ae6,
+        'y' => 0xf0c828,
+        _ => 0x8a94a8,
+    }
+}
+
+// Marks on special floor tiles seen in 3D, u and v go from 0 to 1 across the cell
+pub fn floor_color(tile: char, u: f32, v: f32, time: f32) -> Option<Color>{
+    let color = match tile {
+        // Rows of studs
+        '^' => if (u * 4.0).fract() < 0.5 && (v * 4.0).fract() < 0.5 {
+            Color::new(190, 190, 200).with_alpha(230)
+        } else {
+            Color::new(70, 70, 80).with_alpha(200)
+        },
+        // Slowly moving glow
+        '~' => {
+            let glow = ((u * 6.0 + time * 1.5).sin() + (v * 5.0 - time).cos()) * 0.25 + 0.5;
+            Color::lerp(Color::new(200, 40, 10), Color::new(255, 190, 40), glow).with_alpha(240)
+        },
+        '!' => if is_live(time) {
+            Color::new(120, 220, 255).with_alpha(if (u * 8.0 + v * 3.0 + time * 20.0).sin() > 0.0 { 230 } else { 140 })
+        } else {
+            Color::new(40, 70, 120).with_alpha(150)
+        },
+        // A pulsing ring
+        'c' => {
+            let ring = ((u - 0.5).powi(2) + (v - 0.5).powi(2)).sqrt();
+            if (0.28..0.36).contains(&ring) {
+                Color::new(70, 230, 140).with_alpha((170.0 + 60.0 * (time * 3.0).sin()) as u8)
+            } else {
+                return None;
+            }
+        },
+        _ => return None,
+    };
+    Some(color)
+}
+
+// Draws a texture standing on the floor at a world position, facing the player,
+// hidden behind walls closer than it. `depth` has a distance per pixel and
+// `scale` is its size compared to a wall
+pub fn draw_billboard(
+    framebuffer: &mut Framebuffer,
+    player: &Player,
+    block_size: usize,
+    depth: &[f32],
+    pos: Vec2,
+    scale: f32,
+    texture: &Frame,
+){
+    let hh = (framebuffer.height / 2) as f32;
+    let delta = pos - player.pos;
+    let distance = delta.magnitude();
+    let angle = (delta.y.atan2(delta.x) - player.a + PI).rem_euclid(2.0 * PI) - PI;
+    if angle.abs() > player.fov {
+        return;
+    }
+
+    let center_x = (angle + player.fov / 2.0) / player.fov * framebuffer.width as f32;
+    let stake_height = ((hh + block_size as f32) / distance.max(10.0)) * block_size as f32;
+    let size = stake_height * scale;
+    let floor = hh + player.pitch + stake_height * player.eye_height();
+
+    let start_x = (center_x - size / 2.0).max(0.0) as usize;
+    let end_x = ((center_x + size / 2.0).max(0.0) as usize).min(framebuffer.width);
+    let start_y = (floor - size).max(0.0) as usize;
+    let end_y = (floor.max(0.0) as usize).min(framebuffer.height);
+    for x in start_x..end_x{
+        let u = (x as f32 - (center_x - size / 2.0)) / size;
+        for y in start_y..end_y{
+            if depth[y * framebuffer.width + x] < distance {
+                continue;
+            }
+            let v = (y as f32 - (floor - size)) / size;
+            framebuffer.blend_point(x, y, texture.sample(u, v));
+        }
+    }
+}
+
+// Keys the player carries, shown in the top right corner
+pub fn render_inventory(framebuffer: &mut Framebuffer, player: &Player, textures: &Textures){
+    let size = 32;
+    for (n, kind) in player.inventory.iter().enumerate(){
+        let texture = textures.items[item_index(*kind)].frame(0.0);
+        let xo = framebuffer.width - (n + 1) * (size + 4);
+        fps::render_panel(framebuffer, xo, 4, size, size, Color::new(0, 0, 0).with_alpha(120));
+        for x in 0..size{
+            for y in 0..size{
+                let color = texture.sample(x as f32 / size as f32, y as f32 / size as f32);
+                framebuffer.blend_point(xo + x, 4 + y, color);
+            }
+        }
+    }
+}
+
+// Health bar at the bottom of the screen, above the stamina one
+pub fn render_health(framebuffer: &mut Framebuffer, player: &Player){
+    let width = 150;
+    fps::render_bar(framebuffer, (framebuffer.width - width) / 2, framebuffer.height - 38, width, 10, player.health / MAX_HEALTH, Color::new(220, 40, 50));
+}
+
+// A pip at the top of the screen for each hint left and, while one is shown, an arrow
+// below them pointing the way to the next cell of `path`, the way to the goal from where it was asked
+pub fn render_hint(framebuffer: &mut Framebuffer, level: &Level, player: &Player, hints_left: usize, path: &[(usize, usize)]){
+    // As many pips as fit across the screen
+    let pips = hints_left.min(framebuffer.width / 14);
+    for n in 0..pips{
+        let xo = framebuffer.width / 2 + n * 14 - pips * 7;
+        fps::render_panel(framebuffer, xo + 2, 8, 10, 10, Color::new(255, 210, 60).with_alpha(200));
+    }
+    let Some(next) = heading(path, level.cell(player.pos.x, player.pos.y)) else {
+        return;
+    };
+    let delta = level.center(next) - player.pos;
+    let off = delta.y.atan2(delta.x) - player.a;
+    let xo = framebuffer.width / 2;
+    fps::render_arrow(framebuffer, xo, 52, 26.0, off, Color::new(0, 0, 0).with_alpha(150));
+    fps::render_arrow(framebuffer, xo, 52, 22.0, off, Color::new(255, 210, 60).with_alpha(230));
+}
+
+// Stamina bar at the bottom of the screen, red while the player is out of breath
+pub fn render_stamina(framebuffer: &mut Framebuffer, player: &Player){
+    let width = 150;
+    let color = if player.exhausted() { Color::new(200, 60, 40) } else { Color::new(90, 210, 110) };
+    fps::render_bar(framebuffer, (framebuffer.width - width) / 2, framebuffer.height - 24, width, 10, player.stamina, color);
+}
+
+// Strip above the health bar with the cardinal points around where the player faces, north
+// being up on the minimap. A green mark shows the way to the goal and the heading in degrees is next to it
+pub fn render_compass(framebuffer: &mut Framebuffer, player: &Player, goal: Vec2, numbers: &[Vec<char>]){
+    let width = 240;
+    let height = 20;
+    let xo = (framebuffer.width - width) / 2;
+    let yo = framebuffer.height - 66;
+    // Angle from one end of the strip to the other
+    let span = PI;
+    let offset = |angle: f32| (angle - player.a + PI).rem_euclid(2.0 * PI) - PI;
+    let place = |off: f32| (xo as f32 + (0.5 + off / span) * width as f32) as usize;
+    fps::render_panel(framebuffer, xo, yo, width, height, Color::new(0, 0, 0).with_alpha(150));
+
+    framebuffer.set_current_color(0xffffff);
+    for n in 0..8{
+        let off = offset(n as f32 * PI / 4.0);
+        if off.abs() > span / 2.0 - 0.1{
+            continue;
+        }
+        if n % 2 == 0{
+            fps::render_letter(framebuffer, ['E', 'S', 'W', 'N'][n / 2], place(off) - 5, yo + 5, 2);
+        } else {
+            fps::render_panel(framebuffer, place(off), yo + 7, 1, 6, Color::new(255, 255, 255).with_alpha(160));
+        }
+    }
+    fps::render_panel(framebuffer, xo + width / 2, yo, 1, 4, Color::new(255, 255, 255).with_alpha(220));
+
+    // Kept at the ends of the strip when the goal is out of it
+    let to_goal = goal - player.pos;
+    let off = offset(to_goal.y.atan2(to_goal.x)).clamp(-span / 2.0 + 0.05, span / 2.0 - 0.05);
+    fps::render_panel(framebuffer, place(off) - 3, yo + height - 5, 7, 4, Color::new(80, 220, 120));
+
+    let heading = (player.a + PI / 2.0).to_degrees().round() as usize % 360;
+    fps::render_panel(framebuffer, xo + width + 4, yo - 1, 42, 22, Color::new(0, 0, 0).with_alpha(150));
+    fps::render_number(framebuffer, numbers, heading, xo + width + 7, yo + 1);
+}
+
 pub fn draw_sprite(
     framebuffer: &mut Framebuffer,
+    player: &Player,
     block_size: usize,
     goal: &mut Goal,
     sprite_distance: f32,
-    sprite_center: usize
+    sprite_center: usize,
+    time: f32,
 ){
 
         let hh = (framebuffer.height / 2) as f32;
+        // Floats at the middle of a wall, so it moves against the eye height
+        let stake_height = ((hh + block_size as f32) / sprite_distance) * block_size as f32;
+        let horizon = hh + player.pitch + stake_height * (player.eye_height() - 0.5);
         let sprite_height = ((hh/2.0 + block_size as f32) as f32 / sprite_distance) * block_size as f32;
-        let draw_start_y = (hh - (sprite_height / 2.0)) as usize;
-        let draw_end_y = (hh + (sprite_height / 2.0)) as usize;
+        let draw_start_y = (horizon - (sprite_height / 2.0)) as usize;
+        let draw_end_y = (horizon + (sprite_height / 2.0)) as usize;
 
         let draw_start_x = -sprite_height as i32/2 +sprite_center as i32;
         let mut draw_end_x = sprite_height as i32/2 + sprite_center as i32;
@@ -252,16 +701,11 @@ pub fn draw_sprite(
 
         for x in draw_start_x..draw_end_x{
             if x >= 0{
-                let trans_x = ((x as f32-draw_start_x as f32)/sprite_height as f32)*(goal.sprite.height) as f32;
-                let current_line = goal.sprite.get_line(trans_x as usize);
+                let trans_x = (x as f32-draw_start_x as f32)/sprite_height;
+                let texture = goal.animation.frame(time);
                 for y in draw_start_y..draw_end_y{
-                    let trans_y =
-                    (goal.sprite.height as f32) * (y as f32 - hh + (sprite_height / 2.0)) / sprite_height;
-                    let color = Color::to_hex(&current_line[trans_y as usize]);
-                    if color!=0xFFFFFF{
-                        framebuffer.set_current_color(color);
-                        framebuffer.point(x as usize, y);
-                    }
+                    let trans_y = (y as f32 - horizon + (sprite_height / 2.0)) / sprite_height;
+                    framebuffer.blend_point(x as usize, y, texture.sample(trans_x, trans_y));
 
                 }
             }
@@ -269,8 +713,8 @@ pub fn draw_sprite(
     
 }
 
-pub fn pre_play(screen: &mut usize) {
-    let home = Sprite::new("./src/sprites/screens/start/home_screen.bmp");
+pub fn pre_play(screen: &mut usize, assets: &mut AssetCache) -> Result<(), String> {
+    let home = Animation::still(assets.load("./src/sprites/screens/start/home_screen.bmp")?);
 
     let window_width = 600;
     let window_height = 600;
@@ -287,6 +731,7 @@ pub fn pre_play(screen: &mut usize) {
     .unwrap();
 
     // Starting menu window loop
+    let mut idle = Instant::now();
     while window.is_open() {
         // Cases for closing window
         if window.is_key_down(Key::Escape) {
@@ -296,8 +741,15 @@ pub fn pre_play(screen: &mut usize) {
             *screen = 1;
             break;
         }
+        if !window.get_keys().is_empty() {
+            idle = Instant::now();
+        }
+        if idle.elapsed() >= Duration::from_secs(DEMO_DELAY) {
+            *screen = 4;
+            break;
+        }
 
-        Sprite::render_screen(&mut framebuffer, &home);
+        home.render_screen(&mut framebuffer, 0.0);
 
         window
             .update_with_buffer(
@@ -308,14 +760,72 @@ pub fn pre_play(screen: &mut usize) {
             .unwrap();
         std::thread::sleep(Duration::from_millis(0));
     }
+    Ok(())
+}
+
+// Shown after dying, Enter plays the level again and Escape quits
+pub fn death_screen(screen: &mut usize, assets: &mut AssetCache) -> Result<(), String> {
+    let death = Animation::still(assets.load("./src/sprites/screens/death/death_screen.bmp")?);
+
+    let window_width = 600;
+    let window_height = 600;
+    let framebuffer_width = 600;
+    let framebuffer_height = 600;
+    let mut framebuffer = Framebuffer::new(framebuffer_width, framebuffer_height);
+
+    let mut window = Window::new(
+        "Space Sandwich Eaters",
+        window_width,
+        window_height,
+        WindowOptions::default(),
+    )
+    .unwrap();
+
+    *screen = 0;
+    while window.is_open() {
+        if window.is_key_down(Key::Escape) {
+            break;
+        }
+        if window.is_key_down(Key::Enter) {
+            *screen = 1;
+            break;
+        }
+
+        death.render_screen(&mut framebuffer, 0.0);
+
+        window
+            .update_with_buffer(
+                &framebuffer.color_array_to_u32(),
+                framebuffer_width,
+                framebuffer_height,
+            )
+            .unwrap();
+        std::thread::sleep(Duration::from_millis(16));
+    }
+    Ok(())
 }
 
-pub fn post_play() {
-    let part1 = Sprite::new("./src/sprites/screens/end/Part_1.bmp");
-    let part2 = Sprite::new("./src/sprites/screens/end/Part_2.bmp");
-    let part3 = Sprite::new("./src/sprites/screens/end/Part_3.bmp");
-    let part4 = Sprite::new("./src/sprites/screens/end/Part_4.bmp");
-    let mut animation_frame = 0;
+// Secrets found out of the ones in the level, at the bottom of the screen
+fn render_stats(framebuffer: &mut Framebuffer, numbers: &[Vec<char>], stats: &Stats){
+    let yo = framebuffer.height - 50;
+    fps::render_panel(framebuffer, 240, yo - 8, 120, 34, Color::new(0, 0, 0).with_alpha(150));
+    framebuffer.set_current_color(0xf0c828);
+    fps::render_number(framebuffer, numbers, stats.secrets_found, 256, yo);
+    framebuffer.line(298, yo + 16, 306, yo);
+    fps::render_number(framebuffer, numbers, stats.secrets_total, 320, yo);
+}
+
+pub fn post_play(assets: &mut AssetCache, stats: &Stats) -> Result<(), String> {
+    let numbers = load_maze("./src/mazes/numbers.txt");
+    let ending = Animation::from_frames(
+        vec![
+            assets.load("./src/sprites/screens/end/Part_1.bmp")?,
+            assets.load("./src/sprites/screens/end/Part_2.bmp")?,
+            assets.load("./src/sprites/screens/end/Part_3.bmp")?,
+            assets.load("./src/sprites/screens/end/Part_4.bmp")?,
+        ],
+        &[0.25],
+    );
     let window_width = 600;
     let window_height = 600;
     let framebuffer_width = 600;
@@ -323,7 +833,7 @@ pub fn post_play() {
     let mut framebuffer = Framebuffer::new(framebuffer_width, framebuffer_height);
     let audio_player = AudioPlayer::new("./src/audios/victory.mp3");
 
-    Sprite::render_screen(&mut framebuffer, &part1);
+    ending.render_screen(&mut framebuffer, 0.0);
     let mut window = Window::new(
         "Space Sandwich Eaters",
         window_width,
@@ -333,23 +843,15 @@ pub fn post_play() {
     .unwrap();
 
     audio_player.play();
+    let start = Instant::now();
 
     while window.is_open() {
         if window.is_key_down(Key::Escape) | window.is_key_down(Key::Enter){
             break;
         }
 
-        if animation_frame == 0 {
-            Sprite::render_screen(&mut framebuffer, &part1);
-        } else if animation_frame == 1 {
-            Sprite::render_screen(&mut framebuffer, &part2);         
-        } else if animation_frame == 2 {
-            Sprite::render_screen(&mut framebuffer, &part3);         
-        } else if animation_frame == 3 {
-            Sprite::render_screen(&mut framebuffer, &part4); 
-            animation_frame = -1;        
-        }
-        animation_frame += 1;
+        ending.render_screen(&mut framebuffer, start.elapsed().as_secs_f32());
+        render_stats(&mut framebuffer, &numbers, stats);
 
         window
             .update_with_buffer(
@@ -358,6 +860,7 @@ pub fn post_play() {
                 framebuffer_height,
             )
             .unwrap();
-        std::thread::sleep(Duration::from_millis(250));
+        std::thread::sleep(Duration::from_millis(16));
     }
+    Ok(())
 }
\ No newline at end of file